@@ -0,0 +1,70 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Throughput of hashing, for various file and buffer sizes, and of extracting a sample archive
+//! Run with `cargo bench`, or `cargo bench --no-default-features` for the hashing benchmarks alone
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use file_verification_code::digest::sha256_of_buffered;
+use file_verification_code::{FVC2Hasher, FVCHasher};
+
+const FILE_SIZES: &[usize] = &[4 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+const BUFFER_SIZES: &[usize] = &[8 * 1024, 64 * 1024, 1024 * 1024];
+
+fn hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sha256_of_buffered");
+    for &file_size in FILE_SIZES {
+        let data = vec![0xa5u8; file_size];
+        group.throughput(Throughput::Bytes(file_size as u64));
+        for &buffer_size in BUFFER_SIZES {
+            group.bench_with_input(BenchmarkId::new(format!("buffer {}", buffer_size), file_size), &data, |b, data| {
+                b.iter(|| sha256_of_buffered(&data[..], buffer_size).expect("hashing data"))
+            });
+        }
+    }
+    group.finish();
+
+    // FVC2Hasher::read streams the file through sha256 in fixed-size chunks, like sha256_of_buffered with the default buffer size
+    let mut group = c.benchmark_group("FVC2Hasher::read");
+    for &file_size in FILE_SIZES {
+        let data = vec![0xa5u8; file_size];
+        group.throughput(Throughput::Bytes(file_size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(file_size), &data, |b, data| {
+            b.iter(|| {
+                let mut hasher = FVC2Hasher::new();
+                hasher.read(&data[..]).expect("hashing data");
+                hasher.hex()
+            })
+        });
+    }
+    group.finish();
+}
+
+#[cfg(feature = "extract")]
+fn extraction(c: &mut Criterion) {
+    use file_verification_code::extract::extract_archive;
+
+    let archive = "test_data/archive_hierarchy.tar.gz";
+    let mut group = c.benchmark_group("extract_archive");
+    group.throughput(Throughput::Bytes(std::fs::metadata(archive).expect("reading sample archive").len()));
+    group.bench_function("archive_hierarchy.tar.gz", |b| {
+        b.iter(|| {
+            let tmp = tempdir::TempDir::new("fvc_bench").expect("creating temporary directory");
+            extract_archive(archive, tmp.path()).expect("extracting sample archive");
+        })
+    });
+    group.finish();
+}
+
+#[cfg(feature = "extract")]
+criterion_group!(benches, hashing, extraction);
+#[cfg(not(feature = "extract"))]
+criterion_group!(benches, hashing);
+criterion_main!(benches);
@@ -1,16 +1,17 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::metadata;
 
 use log::*;
 use serde::{Serialize, Deserialize};
-use serde_hex::{SerHex, Strict};
+
+use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct File {
     pub name: String,
     pub size: u64,
-    #[serde(with = "SerHex::<Strict>")]
+    #[serde(with = "sha256_hex")]
     pub sha256: [u8; 32]
 }
 
@@ -39,6 +40,9 @@ impl std::fmt::Display for File {
 }
 
 impl File {
+    /// new records the file at file_path, calculating its size and sha256 unless given
+    /// When the sha256 is calculated, the recorded size is the number of bytes actually hashed, so size and content always agree
+    /// even if the file changed while being read; a warning is logged if that differs from the given or reported size
     pub fn new<P: AsRef<Path>>(file_path: P, size: Option<u64>, sha256: Option<[u8; 32]>) -> std::io::Result<Self> {
         let size = match size {
             Some(size) => size,
@@ -48,16 +52,21 @@ impl File {
             }
         };
 
-        let sha256 = match sha256 {
-            Some(sha256) => sha256,
+        let (sha256, size) = match sha256 {
+            Some(sha256) => (sha256, size),
             None => match get_sha256(&file_path) {
-                Ok(sha256) => sha256,
+                Ok((sha256, hashed)) => {
+                    if hashed != size {
+                        warn!("{} was expected to be {} bytes but {} bytes were hashed, recording {}", file_path.as_ref().display(), size, hashed, hashed);
+                    }
+                    (sha256, hashed)
+                },
                 Err(err) => return std::io::Result::Err(err)
             }
         };
 
-        let name = match file_path.as_ref().file_name() {
-            Some(file_name) => file_name.to_string_lossy().into(),
+        let name = match name_of(file_path.as_ref()) {
+            Some(name) => name,
             None => panic!("file has no file_name")
         };
 
@@ -69,14 +78,102 @@ impl File {
     }
 }
 
+/// Symlink records a symbolic link found while walking
+/// Targets inside the scan root are stored relative to it, so trees stay portable across machines,
+/// while targets outside of it are stored as found and marked external
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Symlink {
+    pub name: String,
+    pub target: PathBuf,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub external: bool
+}
+
+impl Symlink {
+    /// new reads the symbolic link at link_path and records its target relative to root when the target is inside root
+    pub fn new<P: AsRef<Path>, R: AsRef<Path>>(link_path: P, root: R) -> std::io::Result<Self> {
+        let link_path = link_path.as_ref();
+        let target = std::fs::read_link(link_path)?;
+        let name = match name_of(link_path) {
+            Some(name) => name,
+            None => panic!("{:?} has no file_name", link_path)
+        };
+
+        // resolve the target lexically, since it may not exist or may itself be a link
+        let root = std::fs::canonicalize(root)?;
+        let resolved = match link_path.parent() {
+            Some(parent) if target.is_relative() => normalize(&std::fs::canonicalize(parent)?.join(&target)),
+            _ => normalize(&target)
+        };
+
+        match resolved.strip_prefix(&root) {
+            Ok(relative) => Ok(Symlink { name, target: relative.to_path_buf(), external: false }),
+            Err(_) => Ok(Symlink { name, target, external: true })
+        }
+    }
+}
+
+// name_of returns the file name of path, if it has one
+// Names that are not valid UTF-8 are converted lossily, with a warning, since the recorded name then differs from the actual one
+pub(crate) fn name_of(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?;
+    match file_name.to_str() {
+        Some(name) => Some(name.to_string()),
+        None => {
+            let lossy = file_name.to_string_lossy().into_owned();
+            warn!("name of {} is not valid UTF-8, recording it as {:?}", path.display(), lossy);
+            Some(lossy)
+        }
+    }
+}
+
+// normalize lexically removes `.` and `..` components from a path
+fn normalize(path: &Path) -> PathBuf {
+    use std::path::Component;
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component)
+        }
+    }
+
+    normalized
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct Archive {
     pub name: String,
     pub size: u64,
-    #[serde(with = "SerHex::<Strict>")]
+    #[serde(with = "sha256_hex")]
     pub sha256: [u8; 32],
-    pub files: HashMap<PathBuf, File>,
-    pub archives: HashMap<PathBuf, Archive>
+    pub files: BTreeMap<PathBuf, File>,
+    pub archives: BTreeMap<PathBuf, Archive>,
+    /// symlinks found in this archive, they do not contribute to the file verification code
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub symlinks: BTreeMap<PathBuf, Symlink>,
+    /// fvc is the file verification code of this archive's contents, filled in by Collection::merkle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fvc: Option<String>,
+    /// entry_order is every entry's path in the order the archive stores them, when recorded
+    /// Two archives with the same contents but repacked in a different order only differ here
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entry_order: Vec<PathBuf>,
+    /// uncompressed_size is the size of this archive once decompressed, when recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncompressed_size: Option<u64>,
+    /// metadata is the hash of this archive's contents together with its uncompressed_size, filled in by Archive::record_uncompressed_size
+    /// It is informational only and never part of a file verification code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+    /// comment is the archive-level comment, such as a zip's, which may record provenance
+    /// It is informational only and never part of a file verification code, but is folded into the metadata hash when one is recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>
 }
 
 impl std::fmt::Debug for Archive {
@@ -118,13 +215,13 @@ impl Archive {
         let sha256 = match sha256 {
             Some(sha256) => sha256,
             None => match get_sha256(source.as_ref()) {
-                Ok(sha256) => sha256,
+                Ok((sha256, _size)) => sha256,
                 Err(err) => return Err(err)
             }
         };
 
-        let name: String = match source.as_ref().file_name() {
-            Some(file_name) => file_name.to_string_lossy().into(),
+        let name: String = match name_of(source.as_ref()) {
+            Some(name) => name,
             None => panic!("{:?} has no file_name", source.as_ref())
         };
 
@@ -132,8 +229,14 @@ impl Archive {
             name: name,
             size: size,
             sha256: sha256,
-            files: HashMap::new(),
-            archives: HashMap::new()
+            files: BTreeMap::new(),
+            archives: BTreeMap::new(),
+            symlinks: BTreeMap::new(),
+            fvc: None,
+            entry_order: Vec::new(),
+            uncompressed_size: None,
+            metadata: None,
+            comment: None
         })
     }
 
@@ -151,13 +254,95 @@ impl Archive {
         self.archives.insert(archive_path, archive);
         Ok(())
     }
+
+    /// flatten_single returns this archive as just its one file, renamed after the archive, if it holds exactly one file and nothing else
+    /// Otherwise the archive is returned unchanged
+    pub fn flatten_single(mut self) -> Collection {
+        if self.files.len() != 1 || !self.archives.is_empty() || !self.symlinks.is_empty() {
+            return Collection::Archive(self);
+        }
+
+        let file = self.files.pop_first();
+        match file {
+            Some((_path, file)) => Collection::File(File { name: self.name, ..file }),
+            None => Collection::Archive(self)
+        }
+    }
+
+    /// record_uncompressed_size records the size of this archive once decompressed, and folds it, along with the comment if there is one, into the metadata hash
+    /// Archives with the same contents but compressed or padded differently have the same file verification code, but different metadata
+    pub fn record_uncompressed_size(&mut self, uncompressed_size: u64) {
+        use sha2::{Sha256, Digest};
+
+        let mut contents = FVC2Hasher::new();
+        for file in self.files() {
+            contents.read_sha256(file.sha256);
+        }
+
+        let mut metadata = Sha256::new();
+        metadata.update(b"fvc-uncompressed-size");
+        metadata.update([0]);
+        metadata.update(contents.digest());
+        metadata.update(uncompressed_size.to_be_bytes());
+        if let Some(comment) = &self.comment {
+            metadata.update(comment.as_bytes());
+        }
+
+        self.uncompressed_size = Some(uncompressed_size);
+        self.metadata = Some(hex::encode(metadata.finalize()));
+    }
+
+    /// duplicate_members returns every sha256 held by more than one of this archive's own files, with the sorted paths of those files
+    /// Files in nested archives are left to the nested archives, so each archive is reported on its own
+    pub fn duplicate_members(&self) -> BTreeMap<[u8; 32], Vec<PathBuf>> {
+        let mut members: BTreeMap<[u8; 32], Vec<PathBuf>> = BTreeMap::new();
+        for (path, file) in self.files.iter() {
+            members.entry(file.sha256).or_default().push(path.clone());
+        }
+        members.retain(|_, paths| paths.len() > 1);
+        for paths in members.values_mut() {
+            paths.sort();
+        }
+
+        members
+    }
+
+    /// files returns every file under this archive, its own files first, then those of each nested archive, however deeply nested
+    pub fn files(&self) -> impl Iterator<Item = &File> + '_ {
+        let nested: Box<dyn Iterator<Item = &File> + '_> = Box::new(self.archives.values().flat_map(|archive| archive.files()));
+        self.files.values().chain(nested)
+    }
+
+    // merkle fills in the fvc of this archive and every nested archive, and returns the sha256s of all files under it
+    fn merkle(&mut self) -> Vec<[u8; 32]> {
+        let (fvc, sha256s) = merkle_of(&self.files, &mut self.archives);
+        self.fvc = Some(fvc);
+        sha256s
+    }
+
+    // rewrite_paths passes every path recorded in this archive and its nested archives through rewrite
+    fn rewrite_paths<F: Fn(&Path) -> PathBuf>(&mut self, rewrite: &F) {
+        self.files = rewrite_keys(std::mem::take(&mut self.files), rewrite);
+        self.archives = rewrite_keys(std::mem::take(&mut self.archives), rewrite);
+        self.symlinks = rewrite_symlinks(std::mem::take(&mut self.symlinks), rewrite);
+        self.entry_order = self.entry_order.iter().map(|path| rewrite(path)).collect();
+        for archive in self.archives.values_mut() {
+            archive.rewrite_paths(rewrite);
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct Directory {
     directory: PathBuf,
-    pub files: HashMap<PathBuf, File>,
-    pub archives: HashMap<PathBuf, Archive>
+    pub files: BTreeMap<PathBuf, File>,
+    pub archives: BTreeMap<PathBuf, Archive>,
+    /// symlinks found in this directory, they do not contribute to the file verification code
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub symlinks: BTreeMap<PathBuf, Symlink>,
+    /// fvc is the file verification code of this directory's contents, filled in by Collection::merkle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fvc: Option<String>
 }
 
 impl std::fmt::Debug for Directory {
@@ -186,7 +371,7 @@ impl std::fmt::Display for Directory {
 
 impl Directory {
     pub fn new<P: AsRef<Path>>(directory: P) -> Self {
-        Directory { directory: directory.as_ref().to_owned(), files: HashMap::new(), archives: HashMap::new() }
+        Directory { directory: directory.as_ref().to_owned(), files: BTreeMap::new(), archives: BTreeMap::new(), symlinks: BTreeMap::new(), fvc: None }
     }
 
     pub fn add_file<P: AsRef<Path>>(self: &mut Self, file_path: P, size: Option<u64>, sha256: Option<[u8; 32]>) -> std::io::Result<()> {
@@ -203,6 +388,19 @@ impl Directory {
         self.archives.insert(archive_path, archive);
         Ok(())
     }
+
+    /// files returns every file in this directory, its own files first, then those of each archive in it, however deeply nested
+    pub fn files(&self) -> impl Iterator<Item = &File> + '_ {
+        self.files.values().chain(self.archives.values().flat_map(|archive| archive.files()))
+    }
+}
+
+/// CollectionStats sums up the files of a tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollectionStats {
+    pub file_count: u64,
+    /// total_size is the sum of the sizes of the files, archives counting the files they hold rather than their own size
+    pub total_size: u64
 }
 
 #[derive(Debug)]
@@ -213,6 +411,199 @@ pub enum Collection {
     Empty
 }
 
+impl Collection {
+    /// rewrite_paths passes every path recorded in the tree, of directories, files, archive members and symlinks, through rewrite,
+    /// such as to strip a build prefix before the tree is serialized. Names and sha256s are left as they are, so the file verification code does not change
+    pub fn rewrite_paths<F: Fn(&Path) -> PathBuf>(&mut self, rewrite: F) {
+        match self {
+            Collection::Archive(archive) => archive.rewrite_paths(&rewrite),
+            Collection::Directory(directory) => {
+                directory.directory = rewrite(&directory.directory);
+                directory.files = rewrite_keys(std::mem::take(&mut directory.files), &rewrite);
+                directory.archives = rewrite_keys(std::mem::take(&mut directory.archives), &rewrite);
+                directory.symlinks = rewrite_symlinks(std::mem::take(&mut directory.symlinks), &rewrite);
+                for archive in directory.archives.values_mut() {
+                    archive.rewrite_paths(&rewrite);
+                }
+            },
+            Collection::File(_) | Collection::Empty => ()
+        }
+    }
+
+    /// fvc returns the hex file verification code of every file in the tree, from the sha256s it records
+    /// Nothing is read from the filesystem, so the code of a saved tree can be recalculated offline, on any machine
+    pub fn fvc(&self) -> String {
+        hex::encode(self.fvc_bytes())
+    }
+
+    /// fvc_bytes acts like fvc, returning the code as bytes, like FVC2Hasher::sum
+    pub fn fvc_bytes(&self) -> Vec<u8> {
+        let mut hasher: FVC2Hasher = self.sha256s().into_iter().collect();
+        hasher.sum()
+    }
+
+    /// files returns every file in the tree, descending through archives and nested archives, to build manifests or count entries
+    pub fn files(&self) -> impl Iterator<Item = &File> + '_ {
+        let files: Box<dyn Iterator<Item = &File> + '_> = match self {
+            Collection::File(file) => Box::new(std::iter::once(file)),
+            Collection::Archive(archive) => Box::new(archive.files()),
+            Collection::Directory(directory) => Box::new(directory.files()),
+            Collection::Empty => Box::new(std::iter::empty())
+        };
+        files
+    }
+
+    /// stats returns how many files the tree holds, through archives and nested archives, and their total size
+    pub fn stats(&self) -> CollectionStats {
+        self.files().fold(CollectionStats::default(), |stats, file| CollectionStats {
+            file_count: stats.file_count + 1,
+            total_size: stats.total_size + file.size
+        })
+    }
+
+    // sha256s returns the sha256s of all files in the tree, including in archives and nested archives
+    fn sha256s(&self) -> Vec<[u8; 32]> {
+        self.files().map(|file| file.sha256).collect()
+    }
+
+    /// merkle records, on every Directory and Archive node, the file verification code of that node's own subtree
+    /// Like a Merkle tree, changing a file changes the codes of the nodes on its path up to the root, and no others,
+    /// so comparing two trees pinpoints which archives or directories changed
+    pub fn merkle(&mut self) {
+        match self {
+            Collection::Archive(archive) => {
+                archive.merkle();
+            },
+            Collection::Directory(directory) => {
+                let (fvc, _sha256s) = merkle_of(&directory.files, &mut directory.archives);
+                directory.fvc = Some(fvc);
+            },
+            Collection::File(_) | Collection::Empty => ()
+        }
+    }
+}
+
+/// contributions returns the sha256 every file in the collection contributes to the file verification code, by path
+/// Files in archives are keyed by their path in the archive under the path of the archive, so diffing the contributions of two trees pinpoints the files that differ
+pub fn contributions(collection: &Collection) -> BTreeMap<PathBuf, [u8; 32]> {
+    contributing_files(collection).into_iter().map(|(path, file)| (path, file.sha256)).collect()
+}
+
+/// contributing_files returns every file in the collection that contributes to the file verification code, keyed by path like contributions
+pub fn contributing_files(collection: &Collection) -> BTreeMap<PathBuf, &File> {
+    let mut files = BTreeMap::new();
+    match collection {
+        Collection::File(file) => {
+            files.insert(PathBuf::from(&file.name), file);
+        },
+        Collection::Archive(archive) => archive_contributions(&mut files, Path::new(&archive.name), archive),
+        Collection::Directory(directory) => {
+            for (path, file) in directory.files.iter() {
+                files.insert(path.clone(), file);
+            }
+            for (path, archive) in directory.archives.iter() {
+                archive_contributions(&mut files, path, archive);
+            }
+        },
+        Collection::Empty => ()
+    }
+
+    files
+}
+
+/// archive_duplicates returns the duplicate_members of every archive in the collection that has any, keyed by the archive's path like contributions
+pub fn archive_duplicates(collection: &Collection) -> BTreeMap<PathBuf, BTreeMap<[u8; 32], Vec<PathBuf>>> {
+    let mut duplicates = BTreeMap::new();
+    match collection {
+        Collection::Archive(archive) => archive_duplicates_under(&mut duplicates, Path::new(&archive.name), archive),
+        Collection::Directory(directory) => {
+            for (path, archive) in directory.archives.iter() {
+                archive_duplicates_under(&mut duplicates, path, archive);
+            }
+        },
+        Collection::File(_) | Collection::Empty => ()
+    }
+
+    duplicates
+}
+
+/// MemberDiff is how the files contributed by a tree differ from an expected set of sha256s
+#[derive(Debug, Default, PartialEq)]
+pub struct MemberDiff {
+    /// missing are the expected sha256s no file in the tree has
+    pub missing: BTreeSet<[u8; 32]>,
+    /// extra are the files in the tree, by path like contributions, whose sha256 is not expected
+    pub extra: BTreeMap<PathBuf, [u8; 32]>
+}
+
+impl MemberDiff {
+    /// is_exact returns whether the tree holds exactly the expected sha256s, no more and no less
+    pub fn is_exact(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// diff_members compares the sha256s the files of collection, such as an extracted archive, contribute with the expected set
+/// Only contents are compared, so a member that was renamed still matches, while duplicate members with an expected sha256 are not reported
+pub fn diff_members(collection: &Collection, expected: &BTreeSet<[u8; 32]>) -> MemberDiff {
+    let contributions = contributions(collection);
+    let found: BTreeSet<[u8; 32]> = contributions.values().copied().collect();
+
+    MemberDiff {
+        missing: expected.difference(&found).copied().collect(),
+        extra: contributions.into_iter().filter(|(_, sha256)| !expected.contains(sha256)).collect()
+    }
+}
+
+// archive_contributions adds the files of archive, and of the archives in it, under the given prefix
+fn archive_contributions<'a>(files: &mut BTreeMap<PathBuf, &'a File>, prefix: &Path, archive: &'a Archive) {
+    for (path, file) in archive.files.iter() {
+        files.insert(prefix.join(path), file);
+    }
+    for (path, nested) in archive.archives.iter() {
+        archive_contributions(files, &prefix.join(path), nested);
+    }
+}
+
+// archive_duplicates_under adds the duplicate members of archive, and of the archives in it, under the given prefix
+fn archive_duplicates_under(duplicates: &mut BTreeMap<PathBuf, BTreeMap<[u8; 32], Vec<PathBuf>>>, prefix: &Path, archive: &Archive) {
+    let members = archive.duplicate_members();
+    if !members.is_empty() {
+        duplicates.insert(prefix.to_path_buf(), members);
+    }
+    for (path, nested) in archive.archives.iter() {
+        archive_duplicates_under(duplicates, &prefix.join(path), nested);
+    }
+}
+
+// rewrite_keys passes every path in map through rewrite
+fn rewrite_keys<V, F: Fn(&Path) -> PathBuf>(map: BTreeMap<PathBuf, V>, rewrite: &F) -> BTreeMap<PathBuf, V> {
+    map.into_iter().map(|(path, value)| (rewrite(&path), value)).collect()
+}
+
+// rewrite_symlinks passes the path and target of every symlink through rewrite
+fn rewrite_symlinks<F: Fn(&Path) -> PathBuf>(symlinks: BTreeMap<PathBuf, Symlink>, rewrite: &F) -> BTreeMap<PathBuf, Symlink> {
+    symlinks.into_iter().map(|(path, mut symlink)| {
+        symlink.target = rewrite(&symlink.target);
+        (rewrite(&path), symlink)
+    }).collect()
+}
+
+// merkle_of fills in the fvc of the given archives, and returns the fvc of the given files and archives together with the sha256s of all of their files
+fn merkle_of(files: &BTreeMap<PathBuf, File>, archives: &mut BTreeMap<PathBuf, Archive>) -> (String, Vec<[u8; 32]>) {
+    let mut sha256s: Vec<[u8; 32]> = files.values().map(|file| file.sha256).collect();
+    for archive in archives.values_mut() {
+        sha256s.extend(archive.merkle());
+    }
+
+    let mut hasher = FVC2Hasher::new();
+    for sha256 in sha256s.iter() {
+        hasher.read_sha256(*sha256);
+    }
+
+    (hasher.hex(), sha256s)
+}
+
 impl serde::Serialize for Collection {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
@@ -234,26 +625,50 @@ impl serde::Serialize for Collection {
     }
 }
 
-// get_sha256 calculates and returns an array of bytes represeting the sha256 of the given file
-fn get_sha256<P: AsRef<Path>>(path: P) -> std::io::Result<[u8; 32]> {
-    use sha2::{Sha256, Digest};
-    use std::io::Read;
+impl<'de> serde::Deserialize<'de> for Collection {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de> {
+        // a collection is serialized as its content alone, so tell them apart by their fields
+        // Directory is the only one with a directory, and Archive has files where File does not
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Serialized {
+            Directory(Directory),
+            Archive(Archive),
+            File(File)
+        }
 
-    let mut hasher = Sha256::new();
-    let mut file = match std::fs::File::open(path) {
-        Ok(file) => file,
-        Err(err) => return Err(err)
-    };
-    let mut buf = Vec::new();
-    let sha256: [u8; 32] = match file.read_to_end(&mut buf) {
-        Ok(_size) => {
-            hasher.update(buf);
-            hasher.finalize().into()
-        },
-        Err(err) => return Err(err)
-    };
+        Ok(match Option::<Serialized>::deserialize(deserializer)? {
+            None => Collection::Empty,
+            Some(Serialized::Directory(directory)) => Collection::Directory(directory),
+            Some(Serialized::Archive(archive)) => Collection::Archive(archive),
+            Some(Serialized::File(file)) => Collection::File(file)
+        })
+    }
+}
+
+// sha256_hex serializes a sha256 as a lowercase hex string
+// Unlike serde_hex, it also reads owned strings, so trees can be read from any reader and inside untagged enums
+mod sha256_hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(sha256: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(sha256))
+    }
 
-    Ok(sha256)
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let mut sha256 = [0u8; 32];
+        hex::decode_to_slice(&encoded, &mut sha256).map_err(serde::de::Error::custom)?;
+
+        Ok(sha256)
+    }
+}
+
+// get_sha256 calculates and returns an array of bytes represeting the sha256 of the given file, along with the number of bytes hashed
+fn get_sha256<P: AsRef<Path>>(path: P) -> std::io::Result<([u8; 32], u64)> {
+    crate::digest::sha256_of(std::fs::File::open(path)?)
 }
 
 #[cfg(test)]
@@ -279,4 +694,273 @@ mod tests {
         let deserialized: Archive = serde_json::from_str(&serialized).expect("deserializing result");
         assert_eq!(archive, deserialized);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn fvc_of_foo_bar_zap_tree() {
+        let mut archive = Archive::new("test_data/foo_bar_zap.tar.zst", Some(132), Some(hex!("c219699ccc7c7a0ff4770268bc1071664ae16c4b89cad6c3be882efd5f61c50f"))).expect("creating archive");
+        archive.add_file("foo.txt", Some(4), Some(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"))).expect("adding foo");
+        archive.add_file("bar.txt", Some(4), Some(hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730"))).expect("adding bar");
+        archive.add_file("zap.txt", Some(4), Some(hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b"))).expect("adding zap");
+
+        // the code fvc prints for the flat files, read back from a saved tree
+        let serialized = serde_json::to_string(&Collection::Archive(archive)).expect("serializing tree");
+        let tree: Collection = serde_json::from_str(&serialized).expect("deserializing tree");
+        assert_eq!(tree.fvc(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+        assert_eq!(tree.fvc_bytes(), hex::decode(tree.fvc()).unwrap());
+        assert_eq!(Collection::Empty.fvc(), FVC2Hasher::new().hex());
+    }
+
+    #[test]
+    fn files_descend_into_archives() {
+        let mut archive = Archive::new("test_data/foo_bar_zap.tar.gz", Some(150), Some([0u8; 32])).expect("creating archive");
+        archive.add_file("foo.txt", Some(4), Some(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"))).expect("adding foo");
+        let mut nested = Archive::new("bar_zap.tar", Some(10240), Some([1u8; 32])).expect("creating nested archive");
+        nested.add_file("bar.txt", Some(4), Some(hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730"))).expect("adding bar");
+        nested.add_file("zap.txt", Some(4), Some(hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b"))).expect("adding zap");
+        archive.add_archive(PathBuf::from("bar_zap.tar"), nested).expect("adding nested archive");
+
+        let tree = Collection::Archive(archive);
+        let mut names: Vec<&str> = tree.files().map(|file| file.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["bar.txt", "foo.txt", "zap.txt"]);
+        assert_eq!(tree.fvc(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+        assert_eq!(Collection::Empty.files().count(), 0);
+    }
+
+    #[test]
+    fn stats_sum_files_not_archives() {
+        let mut archive = Archive::new("test_data/foo_bar_zap.tar.gz", Some(150), Some([0u8; 32])).expect("creating archive");
+        archive.add_file("foo.txt", Some(4), Some(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"))).expect("adding foo");
+        let mut nested = Archive::new("bar_zap.tar", Some(10240), Some([1u8; 32])).expect("creating nested archive");
+        nested.add_file("bar.txt", Some(4), Some(hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730"))).expect("adding bar");
+        nested.add_file("zap.txt", Some(4), Some(hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b"))).expect("adding zap");
+        archive.add_archive(PathBuf::from("bar_zap.tar"), nested).expect("adding nested archive");
+
+        let mut directory = Directory::new("test_data");
+        directory.add_archive(PathBuf::from("foo_bar_zap.tar.gz"), archive).expect("adding archive");
+        directory.add_file("test_data/flat_files/foo.txt", None, None).expect("adding file");
+        assert_eq!(Collection::Directory(directory).stats(), CollectionStats { file_count: 4, total_size: 16 });
+        assert_eq!(Collection::Empty.stats(), CollectionStats::default());
+    }
+
+    #[test]
+    fn contributions_key_members_by_archive() {
+        let mut nested = Archive::new("test_data/foo.txt.gz", None, None).expect("recording nested archive");
+        nested.add_file("foo.txt", Some(4), Some(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"))).expect("adding foo");
+        let mut archive = Archive::new("test_data/flat_archive.tar", None, None).expect("recording archive");
+        archive.add_file("bar.txt", Some(4), Some(hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730"))).expect("adding bar");
+        archive.add_archive(PathBuf::from("foo.txt.gz"), nested).expect("adding nested archive");
+        let mut directory = Directory::new("test_data");
+        directory.add_file("test_data/flat_files/zap.txt", None, None).expect("adding zap");
+        directory.add_archive(PathBuf::from("test_data/flat_archive.tar"), archive).expect("adding archive");
+
+        let contributions = contributions(&Collection::Directory(directory));
+        let expected: BTreeMap<PathBuf, [u8; 32]> = [
+            ("test_data/flat_files/zap.txt", hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b")),
+            ("test_data/flat_archive.tar/bar.txt", hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730")),
+            ("test_data/flat_archive.tar/foo.txt.gz/foo.txt", hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c")),
+        ].into_iter().map(|(path, sha256)| (PathBuf::from(path), sha256)).collect();
+        assert_eq!(contributions, expected);
+    }
+
+    #[test]
+    fn member_diff_reports_missing_and_extra() {
+        let foo = hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c");
+        let bar = hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730");
+        let zap = hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b");
+        let unexpected = [0xff; 32];
+        let mut archive = Archive::new("release.tar", Some(10240), Some([0; 32])).expect("recording archive");
+        archive.add_file("foo.txt", Some(4), Some(foo)).expect("adding foo");
+        archive.add_file("bar.txt", Some(4), Some(bar)).expect("adding bar");
+        archive.add_file("backdoor.sh", Some(4), Some(unexpected)).expect("adding unexpected member");
+        let collection = Collection::Archive(archive);
+
+        let diff = diff_members(&collection, &[foo, bar, zap].into_iter().collect());
+        assert!(!diff.is_exact());
+        assert_eq!(diff.missing, [zap].into_iter().collect());
+        assert_eq!(diff.extra, [(PathBuf::from("release.tar/backdoor.sh"), unexpected)].into_iter().collect());
+
+        assert!(diff_members(&collection, &[foo, bar, unexpected].into_iter().collect()).is_exact());
+    }
+
+    #[test]
+    fn rewritten_paths_keep_fvc() {
+        let mut archive = Archive::new("/home/builder/out/flat_archive.tar", Some(10240), Some([0; 32])).expect("recording archive");
+        archive.add_file("bar.txt", Some(4), Some(hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730"))).expect("adding bar");
+        let mut directory = Directory::new("/home/builder/out");
+        directory.add_file("/home/builder/out/foo.txt", Some(4), Some(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"))).expect("adding foo");
+        directory.add_archive(PathBuf::from("/home/builder/out/flat_archive.tar"), archive).expect("adding archive");
+        let mut collection = Collection::Directory(directory);
+        collection.merkle();
+        let fvc = |collection: &Collection| match collection {
+            Collection::Directory(directory) => directory.fvc.clone(),
+            other => panic!("expected a directory, found {:?}", other)
+        };
+        let before = fvc(&collection);
+
+        collection.rewrite_paths(|path| match path.strip_prefix("/home/builder") {
+            Ok(relative) => PathBuf::from("$BUILD").join(relative),
+            Err(_) => path.to_path_buf()
+        });
+        let serialized = serde_json::to_string(&collection).expect("serializing tree");
+        assert!(!serialized.contains("/home/builder"), "{}", serialized);
+        let expected: Vec<PathBuf> = ["$BUILD/out/flat_archive.tar/bar.txt", "$BUILD/out/foo.txt"].iter().map(PathBuf::from).collect();
+        assert_eq!(contributions(&collection).into_keys().collect::<Vec<_>>(), expected);
+
+        collection.merkle();
+        assert_eq!(fvc(&collection), before);
+    }
+
+    #[test]
+    fn file_size_is_hashed_size() {
+        // a reported size that disagrees with the content, as if the file grew while being read
+        let file = File::new("test_data/flat_files/foo.txt", Some(999), None).expect("recording file");
+        assert_eq!(file.size, 4);
+        assert_eq!(file.sha256, hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"));
+
+        // a given sha256 is trusted along with the given size
+        let file = File::new("test_data/flat_files/foo.txt", Some(999), Some([0; 32])).expect("recording file");
+        assert_eq!(file.size, 999);
+    }
+
+    #[test]
+    fn serialization_ignores_insertion_order() {
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt", "f.txt"];
+        let mut forward = Directory::new("root");
+        let mut backward = Directory::new("root");
+        for (index, name) in names.iter().enumerate() {
+            forward.add_file(PathBuf::from(name), Some(index as u64), Some([index as u8; 32])).expect("adding file");
+        }
+        for (index, name) in names.iter().enumerate().rev() {
+            backward.add_file(PathBuf::from(name), Some(index as u64), Some([index as u8; 32])).expect("adding file");
+        }
+
+        let forward = serde_json::to_string(&forward).expect("serializing tree");
+        assert_eq!(forward, serde_json::to_string(&backward).expect("serializing tree"));
+        assert!(forward.find("a.txt").unwrap() < forward.find("f.txt").unwrap());
+    }
+
+    #[test]
+    fn same_tree_serializes_identically() {
+        let mut archive = Archive::new("inner.tar", Some(0), Some([9; 32])).expect("creating archive");
+        for (index, name) in ["z.txt", "m.txt", "a.txt"].iter().enumerate() {
+            archive.add_file(PathBuf::from(name), Some(index as u64), Some([index as u8; 32])).expect("adding file");
+        }
+        let mut directory = Directory::new("root");
+        directory.add_archive(PathBuf::from("root/inner.tar"), archive).expect("adding archive");
+        for (index, name) in ["y.txt", "b.txt"].iter().enumerate() {
+            directory.add_file(PathBuf::from(name), Some(index as u64), Some([index as u8 + 3; 32])).expect("adding file");
+        }
+        let tree = Collection::Directory(directory);
+
+        let first = serde_json::to_string(&tree).expect("serializing tree");
+        assert_eq!(first, serde_json::to_string(&tree).expect("serializing tree"));
+        assert_eq!(format!("{:?}", tree), format!("{:?}", tree));
+
+        // and the tree read back serializes to the same bytes
+        let read: Collection = serde_json::from_str(&first).expect("deserializing tree");
+        assert_eq!(serde_json::to_string(&read).expect("serializing tree"), first);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_targets_are_portable() {
+        let root = tempdir::TempDir::new("fvc_symlink_root").expect("creating temporary directory");
+        let outside = tempdir::TempDir::new("fvc_symlink_outside").expect("creating temporary directory");
+        std::fs::create_dir(root.path().join("sub")).expect("creating sub directory");
+        std::fs::write(root.path().join("regular"), "regular\n").expect("writing regular file");
+        std::os::unix::fs::symlink("../regular", root.path().join("sub").join("internal")).expect("creating internal symlink");
+        std::os::unix::fs::symlink(outside.path(), root.path().join("external")).expect("creating external symlink");
+
+        let internal = Symlink::new(root.path().join("sub").join("internal"), root.path()).expect("reading internal symlink");
+        assert_eq!(internal, Symlink { name: "internal".into(), target: PathBuf::from("regular"), external: false });
+
+        let external = Symlink::new(root.path().join("external"), root.path()).expect("reading external symlink");
+        assert_eq!(external, Symlink { name: "external".into(), target: outside.path().to_path_buf(), external: true });
+    }
+
+    // merkle_tree builds a directory holding archive a (which holds archive a1) and archive b, with deep being the content of a1's only file
+    fn merkle_tree(deep: &[u8]) -> Collection {
+        use sha2::{Sha256, Digest};
+
+        let file = |name: &str, content: &[u8]| File { name: name.into(), size: content.len() as u64, sha256: Sha256::digest(content).into() };
+        let archive = |name: &str| Archive::new(name, Some(0), Some([0; 32])).expect("creating archive");
+
+        let mut a1 = archive("a1.tar");
+        a1.files.insert(PathBuf::from("a1/deep.txt"), file("deep.txt", deep));
+        let mut a = archive("a.tar");
+        a.files.insert(PathBuf::from("a/a.txt"), file("a.txt", b"a"));
+        a.archives.insert(PathBuf::from("a/a1.tar"), a1);
+        let mut b = archive("b.tar");
+        b.files.insert(PathBuf::from("b/b.txt"), file("b.txt", b"b"));
+
+        let mut directory = Directory::new("root");
+        directory.files.insert(PathBuf::from("root/root.txt"), file("root.txt", b"root"));
+        directory.archives.insert(PathBuf::from("root/a.tar"), a);
+        directory.archives.insert(PathBuf::from("root/b.tar"), b);
+
+        let mut collection = Collection::Directory(directory);
+        collection.merkle();
+        collection
+    }
+
+    #[test]
+    fn merkle_change_propagates_along_ancestors_only() {
+        let before = merkle_tree(b"deep");
+        let after = merkle_tree(b"changed");
+        let (before, after) = match (before, after) {
+            (Collection::Directory(before), Collection::Directory(after)) => (before, after),
+            _ => panic!("expected directories")
+        };
+
+        fn a(directory: &Directory) -> &Archive { &directory.archives[&PathBuf::from("root/a.tar")] }
+        fn a1(directory: &Directory) -> &Archive { &a(directory).archives[&PathBuf::from("a/a1.tar")] }
+        fn b(directory: &Directory) -> &Archive { &directory.archives[&PathBuf::from("root/b.tar")] }
+
+        assert!(before.fvc.is_some());
+        assert_ne!(before.fvc, after.fvc);
+        assert_ne!(a(&before).fvc, a(&after).fvc);
+        assert_ne!(a1(&before).fvc, a1(&after).fvc);
+        assert_eq!(b(&before).fvc, b(&after).fvc);
+
+        // the root's code is the code of every file in the tree
+        let mut hasher = FVC2Hasher::new();
+        for content in [&b"root"[..], b"a", b"deep", b"b"] {
+            hasher.read_slice(content);
+        }
+        assert_eq!(before.fvc, Some(hasher.hex()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lossy_name_is_warned() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::sync::Mutex;
+
+        // Capture keeps every warning logged, to look for the one about this test's file
+        struct Capture(Mutex<Vec<String>>);
+        impl log::Log for Capture {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.level() <= log::Level::Warn
+            }
+            fn log(&self, record: &log::Record) {
+                if self.enabled(record.metadata()) {
+                    self.0.lock().unwrap().push(record.args().to_string());
+                }
+            }
+            fn flush(&self) {}
+        }
+        static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+        log::set_logger(&CAPTURE).expect("setting logger");
+        log::set_max_level(log::LevelFilter::Warn);
+
+        let tmp = tempdir::TempDir::new("fvc_lossy_name").expect("creating temporary directory");
+        let path = tmp.path().join(std::ffi::OsStr::from_bytes(b"caf\xe9.txt"));
+        std::fs::write(&path, b"foo\n").unwrap();
+
+        let file = File::new(&path, None, None).expect("recording file");
+        assert_eq!(file.name, "caf\u{FFFD}.txt");
+        let warnings = CAPTURE.0.lock().unwrap();
+        assert!(warnings.iter().any(|warning| warning.contains("not valid UTF-8") && warning.contains("caf\u{FFFD}.txt")), "warnings: {:?}", warnings);
+    }
+}
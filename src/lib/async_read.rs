@@ -0,0 +1,65 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! async_read calculates file verification codes of tokio AsyncRead streams, without blocking the runtime
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use sha2::{Sha256, Digest};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+
+// DigestWriter is an AsyncWrite that hashes everything written to it
+struct DigestWriter(Sha256);
+
+impl AsyncWrite for DigestWriter {
+    fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        self.0.update(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// fvc_of_async_readers reads every stream to its end and returns the hex FVC2 code of their contents
+/// Each stream is one file of the code, and is hashed as it is read rather than buffered
+pub async fn fvc_of_async_readers<R, I>(readers: I) -> std::io::Result<String>
+    where
+        R: AsyncRead + Unpin,
+        I: IntoIterator<Item = R> {
+    let mut hasher = FVC2Hasher::new();
+    for mut reader in readers {
+        let mut digest = DigestWriter(Sha256::new());
+        tokio::io::copy(&mut reader, &mut digest).await?;
+        hasher.read_sha256(digest.0.finalize().into());
+    }
+
+    Ok(hasher.hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn foo_bar_zap() {
+        let readers = [Cursor::new(&b"foo\n"[..]), Cursor::new(&b"bar\n"[..]), Cursor::new(&b"zap\n"[..])];
+        let fvc = fvc_of_async_readers(readers).await.expect("hashing readers");
+        assert_eq!(fvc, "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+    }
+}
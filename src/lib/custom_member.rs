@@ -0,0 +1,91 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! custom_member calculates a code like FVC2, but with every member hashed by a function of the caller's choosing, such as a keyed HMAC
+//! The members are still folded with sha256, sorted, behind the FVC2 prefix, but the result is not a standard FVC2 code:
+//! it only matches codes calculated with the same member function, and, for a keyed function, the same key
+
+use std::io::Read;
+
+use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+
+/// CustomMemberHasher hashes every member it reads with its member function, in place of sha256
+pub struct CustomMemberHasher<F: Fn(&[u8]) -> [u8; 32]> {
+    fvc: FVC2Hasher,
+    member: F,
+}
+
+impl<F: Fn(&[u8]) -> [u8; 32]> CustomMemberHasher<F> {
+    /// create a new CustomMemberHasher that hashes members with the given function
+    pub fn new(member: F) -> Self {
+        CustomMemberHasher { fvc: FVC2Hasher::new(), member }
+    }
+}
+
+/// Implements FVCHasher by passing the whole content of every reader to the member function
+impl<F: Fn(&[u8]) -> [u8; 32]> FVCHasher for CustomMemberHasher<F> {
+    fn read(&mut self, mut reader: impl Read) -> std::result::Result<usize, std::io::Error> {
+        let mut buf = Vec::new();
+        let size = reader.read_to_end(&mut buf)?;
+        self.fvc.read_sha256((self.member)(&buf));
+
+        Ok(size)
+    }
+
+    fn sum(&mut self) -> Vec<u8> {
+        self.fvc.sum()
+    }
+
+    fn hex(&mut self) -> String {
+        self.fvc.hex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Sha256, Digest};
+
+    // hmac_sha256 is HMAC-SHA256 (RFC 2104) for keys of up to one block
+    fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+        let mut block = [0u8; 64];
+        block[..key.len()].copy_from_slice(key);
+        let inner: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+        let outer: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+
+        let inner = Sha256::new().chain_update(inner).chain_update(message).finalize();
+        Sha256::new().chain_update(outer).chain_update(inner).finalize().into()
+    }
+
+    #[test]
+    fn keyed_member_hash() {
+        // RFC 4231 test case 2
+        assert_eq!(hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+
+        let fvc = |key: &'static [u8]| {
+            let mut hasher = CustomMemberHasher::new(move |data: &[u8]| hmac_sha256(key, data));
+            for content in [&b"foo\n"[..], &b"bar\n"[..], &b"zap\n"[..]] {
+                hasher.read(content).expect("reading content");
+            }
+            hasher.hex()
+        };
+
+        assert_eq!(fvc(b"first key"), fvc(b"first key"));
+        assert_ne!(fvc(b"first key"), fvc(b"second key"));
+        assert_ne!(fvc(b"first key"), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+
+        // hashing members with sha256 gives back the standard code
+        let mut sha256 = CustomMemberHasher::new(|data: &[u8]| Sha256::digest(data).into());
+        for content in [&b"foo\n"[..], &b"bar\n"[..], &b"zap\n"[..]] {
+            sha256.read(content).expect("reading content");
+        }
+        assert_eq!(sha256.hex(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! digest hashes readers to the sha256s that file verification codes are made of
+
+use std::io::Read;
+use sha2::{Sha256, Digest};
+use sha2::digest::Output;
+
+/// DEFAULT_BUFFER_SIZE is the buffer size sha256_of reads with
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// sha256_of reads reader to its end and returns its sha256, along with the number of bytes read
+pub fn sha256_of<R: Read>(reader: R) -> std::io::Result<([u8; 32], u64)> {
+    sha256_of_buffered(reader, DEFAULT_BUFFER_SIZE)
+}
+
+/// sha256_of_buffered acts like sha256_of, reading buffer_size bytes at a time
+/// The result does not depend on buffer_size, only how fast it is calculated
+pub fn sha256_of_buffered<R: Read>(reader: R, buffer_size: usize) -> std::io::Result<([u8; 32], u64)> {
+    let (sha256, size) = digest_of_buffered::<Sha256, R>(reader, buffer_size)?;
+    Ok((sha256.into(), size))
+}
+
+/// digest_of_buffered acts like sha256_of_buffered with any digest D, for hashers other than sha256
+pub fn digest_of_buffered<D: Digest, R: Read>(mut reader: R, buffer_size: usize) -> std::io::Result<(Output<D>, u64)> {
+    let mut hasher = D::new();
+    let mut buffer = vec![0u8; buffer_size.max(1)];
+    let mut size = 0u64;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        };
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok((hasher.finalize(), size))
+}
+
+/// sha256_of_normalized_text returns the sha256 of reader with trailing spaces and tabs stripped from every line, and the newlines at its end dropped,
+/// so text differing only in the trailing whitespace editors trim or add hashes the same. Lines are split at `\n` only, a `\r` counts as content
+/// Content with a NUL byte is binary and gives None, to be hashed as it is
+pub fn sha256_of_normalized_text<R: Read>(mut reader: R) -> std::io::Result<Option<[u8; 32]>> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; DEFAULT_BUFFER_SIZE];
+    let mut normalized = Vec::with_capacity(DEFAULT_BUFFER_SIZE);
+    // whitespace and newlines are held back until content follows them, and dropped if none does
+    let mut pending_whitespace = Vec::new();
+    let mut pending_newlines = 0usize;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        };
+
+        normalized.clear();
+        for &byte in &buffer[..read] {
+            match byte {
+                0 => return Ok(None),
+                b' ' | b'\t' => pending_whitespace.push(byte),
+                b'\n' => {
+                    pending_whitespace.clear();
+                    pending_newlines += 1;
+                },
+                _ => {
+                    normalized.resize(normalized.len() + pending_newlines, b'\n');
+                    normalized.append(&mut pending_whitespace);
+                    normalized.push(byte);
+                    pending_newlines = 0;
+                }
+            }
+        }
+        hasher.update(&normalized);
+    }
+
+    Ok(Some(hasher.finalize().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn buffer_size_does_not_change_sha256() {
+        let data = b"foo\n".repeat(1000);
+        for buffer_size in [1, 3, 4096, DEFAULT_BUFFER_SIZE] {
+            let (sha256, size) = sha256_of_buffered(&data[..], buffer_size).expect("hashing data");
+            assert_eq!(sha256, sha256_of(&data[..]).unwrap().0);
+            assert_eq!(size, 4000);
+        }
+        assert_eq!(sha256_of(&b"foo\n"[..]).unwrap().0, hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"));
+    }
+
+    #[test]
+    fn normalized_text_ignores_trailing_whitespace() {
+        let normalized = |text: &[u8]| sha256_of_normalized_text(text).expect("hashing text");
+        let expected = Some(sha256_of(&b"foo\n\n  bar\tbaz"[..]).unwrap().0);
+        assert_eq!(normalized(b"foo\n\n  bar\tbaz"), expected);
+        assert_eq!(normalized(b"foo \t\n \n  bar\tbaz  \n\n"), expected);
+        assert_ne!(normalized(b"foo\n  bar\tbaz"), expected);
+        assert_eq!(normalized(b"foo \n\0"), None);
+    }
+}
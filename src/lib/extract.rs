@@ -11,11 +11,17 @@
 //! extract calls libarchive to extract the given archive
 
 use std::fs::File;
-use compress_tools::{uncompress_archive, Ownership, Result, Error};
-use std::path::Path;
+use compress_tools::{list_archive_files, uncompress_archive, uncompress_data, Ownership, Result, Error};
+use std::path::{Path, PathBuf};
 
 /// extract_archive uses libarchive to extract src to dst
+/// With the unrar feature, RAR archives are extracted with unrar instead, as libarchive cannot read every RAR archive
 pub fn extract_archive<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> Result<()> {
+    #[cfg(feature = "unrar")]
+    if is_rar(&src)? {
+        return extract_rar(src, dst).map_err(Error::Io);
+    }
+
     let source = match File::open(src) {
         Ok(file) => file,
         Err(err) => return Err(Error::Io(err))
@@ -24,10 +30,182 @@ pub fn extract_archive<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> Result
     uncompress_archive(source, dst.as_ref(), Ownership::Ignore)
 }
 
+/// extract_package_payload extracts the files a Debian package installs to dst, from its data archive, leaving out its control archive and format version
+/// A package without a data archive is extracted as a whole, like extract_archive. RPM packages need no such care, as libarchive only extracts their cpio payload
+pub fn extract_package_payload<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> Result<()> {
+    let package = tempdir::TempDir::new("fvc_package")?;
+    extract_archive(&src, package.path())?;
+
+    let mut members = Vec::new();
+    for entry in std::fs::read_dir(package.path())? {
+        members.push(entry?.path());
+    }
+    let payload = members.into_iter().find(|member| match member.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.starts_with("data.tar"),
+        None => false
+    });
+
+    match payload {
+        Some(payload) => extract_archive(payload, dst),
+        None => {
+            log::warn!("{} has no data archive, extracting all of it", src.as_ref().display());
+            extract_archive(src, dst)
+        }
+    }
+}
+
+// RAR 4 archives start with these bytes followed by a 0, RAR 5 archives by a 1 and a 0
+#[cfg(feature = "unrar")]
+const RAR_MAGIC: [u8; 6] = *b"Rar!\x1a\x07";
+
+// is_rar returns whether src starts like a RAR archive, whatever its extension
+#[cfg(feature = "unrar")]
+fn is_rar<S: AsRef<Path>>(src: S) -> std::io::Result<bool> {
+    use std::io::Read;
+
+    let mut magic = Vec::with_capacity(RAR_MAGIC.len());
+    File::open(src)?.take(RAR_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    Ok(magic == RAR_MAGIC)
+}
+
+/// extract_rar uses unrar to extract every file of the RAR archive src to dst
+/// Directories are created as their files are extracted, and empty directories, like everywhere else, do not contribute to the code
+#[cfg(feature = "unrar")]
+pub fn extract_rar<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> std::io::Result<()> {
+    let mut archive = unrar::Archive::new(src.as_ref()).open_for_processing().map_err(std::io::Error::other)?;
+    while let Some(header) = archive.read_header().map_err(std::io::Error::other)? {
+        archive = match header.entry().is_file() {
+            true => header.extract_with_base(dst.as_ref()),
+            false => header.skip()
+        }.map_err(std::io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+/// ZstdDictionary is a dictionary that zstd streams were compressed with, shared by every archive it is used for
+#[cfg(feature = "zstd")]
+#[derive(Clone)]
+pub struct ZstdDictionary(std::sync::Arc<[u8]>);
+
+#[cfg(feature = "zstd")]
+impl std::fmt::Debug for ZstdDictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ZstdDictionary({} bytes)", self.0.len())
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl ZstdDictionary {
+    /// read loads the dictionary from the given file
+    pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(ZstdDictionary(std::fs::read(path)?.into()))
+    }
+
+    /// new uses the given bytes as the dictionary
+    pub fn new(dictionary: &[u8]) -> Self {
+        ZstdDictionary(dictionary.into())
+    }
+}
+
+// every zstd frame starts with these bytes
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// extract_archive_with_dictionary acts like extract_archive, but decompresses src with the given dictionary first if it is a zstd stream
+/// libarchive cannot use zstd dictionaries, so it is only given the decompressed stream
+#[cfg(feature = "zstd")]
+pub fn extract_archive_with_dictionary<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D, dictionary: &ZstdDictionary) -> Result<()> {
+    use std::io::{BufRead, BufReader};
+
+    let mut source = BufReader::new(File::open(&src)?);
+    if !source.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        return extract_archive(src, dst);
+    }
+
+    let decoder = zstd::stream::read::Decoder::with_dictionary(source, &dictionary.0)?;
+    uncompress_archive(decoder, dst.as_ref(), Ownership::Ignore)
+}
+
+/// list_entries uses libarchive to list the path of every entry of src, in the order they are stored
+pub fn list_entries<S: AsRef<Path>>(src: S) -> Result<Vec<PathBuf>> {
+    let source = File::open(src)?;
+    let entries = list_archive_files(source)?;
+
+    Ok(entries.into_iter().map(PathBuf::from).collect())
+}
+
+/// uncompressed_size uses libarchive to count the bytes of src once its compression, if any, is removed
+/// For a tar.gz this is the size of the tar, while archives that compress their members, like zip, count as they are
+pub fn uncompressed_size<S: AsRef<Path>>(src: S) -> Result<u64> {
+    let source = File::open(src)?;
+    let size = uncompress_data(source, std::io::sink())?;
+
+    Ok(size as u64)
+}
+
+// every zip ends with an end of central directory record, starting with these bytes, of at least EOCD_SIZE bytes followed by the comment
+const EOCD_MAGIC: [u8; 4] = [b'P', b'K', 0x05, 0x06];
+const EOCD_SIZE: usize = 22;
+
+/// zip_comment returns the archive-level comment of src, if it is a zip with one
+/// libarchive does not report it, so it is read from the end of central directory record directly. Comments that are not valid UTF-8 are converted lossily
+pub fn zip_comment<S: AsRef<Path>>(src: S) -> std::io::Result<Option<String>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    // the record is within the last EOCD_SIZE bytes plus the longest possible comment
+    let mut file = File::open(src)?;
+    let len = file.metadata()?.len();
+    let tail_len = len.min((EOCD_SIZE + u16::MAX as usize) as u64);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = Vec::with_capacity(tail_len as usize);
+    file.read_to_end(&mut tail)?;
+
+    // look for the last record whose comment runs exactly to the end of the file
+    for start in (0..tail.len().saturating_sub(EOCD_SIZE - 1)).rev() {
+        if !tail[start..].starts_with(&EOCD_MAGIC) {
+            continue;
+        }
+        let comment_len = u16::from_le_bytes([tail[start + 20], tail[start + 21]]) as usize;
+        if start + EOCD_SIZE + comment_len == tail.len() {
+            return Ok(match comment_len {
+                0 => None,
+                _ => Some(String::from_utf8_lossy(&tail[start + EOCD_SIZE..]).into_owned())
+            });
+        }
+    }
+
+    Ok(None)
+}
+
+// the first bytes of the compressed streams recognized by compressed_stream, with the name of their compression
+const STREAM_MAGIC: &[(&str, &[u8])] = &[("gzip", &[0x1f, 0x8b]), ("zstd", &ZSTD_MAGIC)];
+
+/// compressed_stream returns the compression of src, if it starts like a gzip or zstd stream
+/// This tells a file that failed to extract because it is a truncated or corrupt stream apart from one that is not an archive at all
+pub fn compressed_stream<S: AsRef<Path>>(src: S) -> std::io::Result<Option<&'static str>> {
+    use std::io::Read;
+
+    let mut magic = Vec::with_capacity(4);
+    File::open(src)?.take(4).read_to_end(&mut magic)?;
+
+    Ok(STREAM_MAGIC.iter().find(|(_, prefix)| magic.starts_with(prefix)).map(|(compression, _)| *compression))
+}
+
 // list of known archive extensions
-const VALID_EXTENSIONS: &'static [&'static str] = &["ar", "arj", "cpio", "dump", "jar", "7z", "zip", "pack", "pack2000", "tar", "bz2", "gz", "lzma", "snz", "xz", "z", "tgz", "rpm", "gem", "deb", "whl", "apk", "zst"];
+const VALID_EXTENSIONS: &'static [&'static str] = &["ar", "arj", "cpio", "dump", "jar", "7z", "zip", "pack", "pack2000", "tar", "bz2", "gz", "lzma", "snz", "xz", "z", "tgz", "rpm", "gem", "deb", "whl", "apk", "zst", "lz4", "rar"];
+
+/// archive_extensions returns every file extension is_extractable considers an archive
+/// Files ending in pack or img are only considered archives after looking at the files around them or their first bytes
+pub fn archive_extensions() -> Vec<&'static str> {
+    let mut extensions = VALID_EXTENSIONS.to_vec();
+    extensions.push("img");
+
+    extensions
+}
 
 /// is_extractable looks at the file extension, and possibly the context of files around it, to guess whether that file is an extractable file
+/// Only the last extension is looked at, even for compound ones like tar.bz2.gz: libarchive peels every compression filter, however many are stacked,
+/// before detecting the format underneath, so the whole chain is extracted at once either way
 pub fn is_extractable<P: AsRef<Path>>(path: P) -> u8 {
     match path.as_ref().extension() {
         None => 0,
@@ -59,6 +237,11 @@ pub fn is_extractable<P: AsRef<Path>>(path: P) -> u8 {
                         } else {
                             return 100
                         }
+                    } else if s == "img" { // .img is used for initramfs images, but also for disk images which are not archives
+                        return match is_initramfs(&path) {
+                            true => 100,
+                            false => 0
+                        };
                     } else {
                         for valid in VALID_EXTENSIONS {
                             if s == *valid {
@@ -73,3 +256,103 @@ pub fn is_extractable<P: AsRef<Path>>(path: P) -> u8 {
         }
     }
 }
+
+// magic bytes an initramfs image may start with, either a cpio archive or one of the compressions the kernel supports for it
+const INITRAMFS_MAGIC: &[&[u8]] = &[
+    b"070701", b"070702", b"070707", // cpio newc, crc and odc
+    &[0x1f, 0x8b], // gzip
+    &[0xfd, b'7', b'z', b'X', b'Z', 0x00], // xz
+    &[0x28, 0xb5, 0x2f, 0xfd], // zstd
+    b"BZh", // bzip2
+    &[0x02, 0x21, 0x4c, 0x18], // lz4 legacy
+    &[0x5d, 0x00, 0x00] // lzma
+];
+
+// is_initramfs looks at the first bytes of a file to tell whether it is a, possibly compressed, cpio archive
+fn is_initramfs<P: AsRef<Path>>(path: P) -> bool {
+    use std::io::Read;
+
+    let mut magic = Vec::with_capacity(6);
+    match File::open(path) {
+        Ok(file) => match file.take(6).read_to_end(&mut magic) {
+            Ok(_) => INITRAMFS_MAGIC.iter().any(|prefix| magic.starts_with(prefix)),
+            Err(_) => false
+        },
+        Err(_) => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_archive_extensions() {
+        let extensions = archive_extensions();
+        for extension in ["zip", "tar", "zst"] {
+            assert!(extensions.contains(&extension), "{} is missing", extension);
+            assert_eq!(is_extractable(format!("archive.{}", extension)), 100);
+        }
+    }
+
+    #[test]
+    fn zip_comment_is_read() {
+        assert_eq!(zip_comment("test_data/commented_archive.zip").unwrap().as_deref(), Some("built from commit 0123abc by release job 42"));
+        assert_eq!(zip_comment("test_data/nested_archive.zip").unwrap(), None);
+        assert_eq!(zip_comment("test_data/flat_archive.tar.gz").unwrap(), None);
+        assert_eq!(zip_comment("test_data/flat_files/bar.txt").unwrap(), None);
+    }
+
+    #[test]
+    fn package_payload_leaves_out_control() {
+        let tmp = tempdir::TempDir::new("fvc_package_payload").expect("creating temporary directory");
+        extract_package_payload("test_data/hello.deb", tmp.path()).expect("extracting package payload");
+
+        let hello = tmp.path().join("usr/share/hello");
+        assert_eq!(std::fs::read_to_string(hello.join("hello.txt")).unwrap(), "hello\n");
+        assert_eq!(std::fs::read_to_string(hello.join("world.txt")).unwrap(), "world\n");
+        for member in ["debian-binary", "control.tar.gz", "data.tar.gz", "control"] {
+            assert!(!tmp.path().join(member).exists(), "{} was extracted", member);
+        }
+    }
+
+    #[cfg(feature = "unrar")]
+    #[test]
+    fn rar_extracted_with_unrar() {
+        use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+
+        let tmp = tempdir::TempDir::new("fvc_unrar").expect("creating temporary directory");
+        assert!(is_rar("test_data/flat_archive.rar").unwrap());
+        extract_archive("test_data/flat_archive.rar", tmp.path()).expect("extracting rar");
+
+        let mut hasher = FVC2Hasher::new();
+        for name in ["foo.txt", "bar.txt", "zap.txt"] {
+            hasher.read_sha256(crate::digest::sha256_of(File::open(tmp.path().join(name)).unwrap()).unwrap().0);
+        }
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+        assert!(!is_rar("test_data/flat_archive.tar").unwrap());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_dictionary() {
+        use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+
+        let dictionary = ZstdDictionary::new(&b"foo\nbar\nzap\n".repeat(64));
+        let tar = std::fs::read("test_data/flat_archive.tar").unwrap();
+        let compressed = zstd::bulk::Compressor::with_dictionary(3, &dictionary.0).and_then(|mut compressor| compressor.compress(&tar)).expect("compressing archive");
+
+        let tmp = tempdir::TempDir::new("fvc_zstd_dictionary").expect("creating temporary directory");
+        let archive = tmp.path().join("flat_archive.tar.zst");
+        std::fs::write(&archive, compressed).unwrap();
+        let extracted = tmp.path().join("extracted");
+        std::fs::create_dir(&extracted).unwrap();
+        extract_archive_with_dictionary(&archive, &extracted, &dictionary).expect("extracting archive");
+
+        let mut hasher = FVC2Hasher::new();
+        for name in ["foo.txt", "bar.txt", "zap.txt"] {
+            hasher.read_sha256(crate::digest::sha256_of(File::open(extracted.join(name)).unwrap()).unwrap().0);
+        }
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+}
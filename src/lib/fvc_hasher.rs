@@ -1,9 +1,31 @@
 use std::io::Read;
+use std::path::Path;
 
 /// FVCHasher reads in data, calculates and stores its sha256, and then returns the file verification code
 pub trait FVCHasher {
     /// read takes a reader, such as an open file, calculates its sha256 and stores for later output
     fn read(&mut self, reader: impl Read) -> Result<usize, std::io::Error>;
+    /// read_file opens the file at path and reads it like read, returning the number of bytes read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use file_verification_code::FVCHasher;
+    /// use file_verification_code::FVC2Hasher;
+    /// let tmp = tempdir::TempDir::new("read_file").unwrap();
+    /// let path = tmp.path().join("foo.txt");
+    /// std::fs::write(&path, "foo\n").unwrap();
+    ///
+    /// let mut hasher = FVC2Hasher::new();
+    /// assert_eq!(hasher.read_file(&path).unwrap(), 4);
+    ///
+    /// let mut direct = FVC2Hasher::new();
+    /// direct.read(&b"foo\n"[..]).unwrap();
+    /// assert_eq!(hasher.hex(), direct.hex());
+    /// ```
+    fn read_file<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, std::io::Error> {
+        self.read(std::fs::File::open(path)?)
+    }
     /// sum calculates the file verification code of the currently held hashes
     fn sum(&mut self) -> Vec<u8>;
     /// hex behaves like sum, except returns the file verification code as a hex string
@@ -17,9 +17,20 @@ mod fvc_hasher;
 pub use fvc_hasher::{FVCHasher, FVCSha256Hasher};
 
 mod version_2;
-pub use version_2::FVC2Hasher;
+pub use version_2::{FVC2Hasher, FVCGenericHasher, CodeDigest, FVC2_CODE_LEN, fvc_code_len, parse_fvc, FvcParseError, VersionFold, SUPPORTED_VERSIONS, fvc_of_entries, fvc_from_paths};
+
+pub mod digest;
+pub mod whole_stream;
+pub mod custom_member;
+pub mod shared;
+pub mod progress;
+pub mod policy;
 
 #[cfg(feature = "extract")]
 pub mod extract;
-#[cfg(feature = "extract")]
-pub mod archive_tree;
\ No newline at end of file
+#[cfg(any(feature = "extract", feature = "tar"))]
+pub mod archive_tree;
+#[cfg(feature = "tar")]
+pub mod tar_stream;
+#[cfg(feature = "tokio")]
+pub mod async_read;
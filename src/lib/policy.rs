@@ -0,0 +1,68 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! policy decides which files are extracted as archives, and which are hashed as they are
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// ExtractPolicy is how to decide what files to try extracting
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractPolicy {
+    /// Only try to extract files with extensions that look like archives
+    Extension,
+    /// Try to extract every file
+    All,
+    /// Don't extract, treat archives as binary files
+    None    
+}
+
+/// would_extract predicts, without touching anything but the files around path, whether a file would be tried as an archive under policy
+/// extra_extensions are considered archive extensions on top of those of extract::archive_extensions
+/// This is the decision fvc makes, including looking for git pack files, so a file it returns true for that turns out not to be an archive is still hashed as a file
+#[cfg(feature = "extract")]
+pub fn would_extract(path: &std::path::Path, policy: ExtractPolicy, extra_extensions: &[&str]) -> bool {
+    match policy {
+        ExtractPolicy::None => false,
+        ExtractPolicy::All => true,
+        ExtractPolicy::Extension => {
+            let extra = match path.extension() {
+                Some(extension) => extra_extensions.iter().any(|extra| extension == *extra),
+                None => false
+            };
+
+            extra || crate::extract::is_extractable(path) > 0
+        }
+    }
+}
+
+#[cfg(all(test, feature = "extract"))]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn would_extract_by_policy() {
+        let zip = Path::new("archive.zip");
+        let txt = Path::new("notes.txt");
+        // a pack file in an objects directory may be a git pack rather than a pack200 archive, which is still tried without an idx next to it
+        let pack = Path::new("objects/pack-0123.pack");
+
+        for path in [zip, txt, pack] {
+            assert!(!would_extract(path, ExtractPolicy::None, &[]), "{}", path.display());
+            assert!(would_extract(path, ExtractPolicy::All, &[]), "{}", path.display());
+        }
+        assert!(would_extract(zip, ExtractPolicy::Extension, &[]));
+        assert!(!would_extract(txt, ExtractPolicy::Extension, &[]));
+        assert!(would_extract(pack, ExtractPolicy::Extension, &[]));
+        assert!(would_extract(txt, ExtractPolicy::Extension, &["txt"]));
+    }
+}
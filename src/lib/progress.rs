@@ -0,0 +1,32 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! progress lets embedders follow a file verification code being calculated, to drive their own progress displays
+//! fvc's `--events` stream is one implementation of ProgressReporter
+
+use std::path::Path;
+
+/// ProgressReporter is told about every file hashed and archive extracted while processing, and about the resulting code
+/// Every method does nothing by default, so implementers only implement what they display
+/// Reporters may be shared by threads processing in parallel, so they take &self and must be Send and Sync
+pub trait ProgressReporter: Send + Sync {
+    /// on_file is called for every file hashed that will be part of the code, with its sha256 and size
+    fn on_file(&self, _path: &Path, _sha256: &[u8; 32], _size: u64) {}
+    /// on_archive is called for every archive extracted once its contents were processed, with the archive's own sha256
+    fn on_archive(&self, _path: &Path, _sha256: &[u8; 32]) {}
+    /// on_done is called once with the hex code, after everything was processed
+    fn on_done(&self, _fvc: &str) {}
+}
+
+impl std::fmt::Debug for dyn ProgressReporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressReporter")
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! shared lets several threads add members to one file verification code without managing locking themselves
+//! FVC2 sorts its members, so the order threads add them in does not change the code
+
+use std::io::Read;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+use crate::digest::sha256_of;
+
+/// SharedFVCHasher is an FVC2Hasher behind a lock, to be shared by reference, or in an Arc, between worker threads
+pub struct SharedFVCHasher {
+    fvc: Mutex<FVC2Hasher>,
+}
+
+impl SharedFVCHasher {
+    /// create a new SharedFVCHasher with no members
+    pub fn new() -> Self {
+        SharedFVCHasher { fvc: Mutex::new(FVC2Hasher::new()) }
+    }
+
+    /// read_sha256 adds the given sha256 as a member
+    pub fn read_sha256(&self, sha256: [u8; 32]) {
+        self.lock().read_sha256(sha256);
+    }
+
+    /// read hashes reader to its end and adds its sha256 as a member, returning the number of bytes read
+    /// The lock is only taken once reader is hashed, so threads hash in parallel
+    pub fn read(&self, reader: impl Read) -> std::io::Result<usize> {
+        let (sha256, size) = sha256_of(reader)?;
+        self.read_sha256(sha256);
+
+        Ok(size as usize)
+    }
+
+    /// hex returns the hex code of the members added so far
+    pub fn hex(&self) -> String {
+        self.lock().hex()
+    }
+
+    /// into_inner returns the FVC2Hasher holding every member added, once the threads are done with it
+    pub fn into_inner(self) -> FVC2Hasher {
+        self.fvc.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // lock locks the hasher, a thread panicking while holding the lock leaves at worst one member unadded, so a poisoned lock is used as it is
+    fn lock(&self) -> MutexGuard<'_, FVC2Hasher> {
+        self.fvc.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for SharedFVCHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Sha256, Digest};
+
+    #[test]
+    fn threads_give_fvc_of_union() {
+        let shared = SharedFVCHasher::new();
+        let mut expected = FVC2Hasher::new();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let shared = &shared;
+                scope.spawn(move || {
+                    for member in 0..100 {
+                        let content = format!("thread {} member {}\n", thread, member);
+                        match member % 2 {
+                            0 => shared.read_sha256(Sha256::digest(content.as_bytes()).into()),
+                            _ => { shared.read(content.as_bytes()).expect("reading content"); }
+                        }
+                    }
+                });
+            }
+        });
+        for thread in 0..8 {
+            for member in 0..100 {
+                expected.read_slice(format!("thread {} member {}\n", thread, member).as_bytes());
+            }
+        }
+
+        assert_eq!(shared.hex(), expected.hex());
+        assert_eq!(shared.into_inner().len(), 800);
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! tar_stream hashes the members of tar archives, plain or gzipped, as they are read from the archive
+//! Unlike extracting with libarchive, this is pure Rust and never writes members to disk
+
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use flate2::read::GzDecoder;
+use log::*;
+
+use crate::archive_tree::{Archive, File};
+
+// every gzip stream starts with these bytes
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// hash_tar reads the tar archive at path, gzipped or not, in a single pass and returns its tree with the sha256 of every regular file
+/// Members that are archives themselves are hashed as files rather than opened, and entries that are not regular files are skipped
+pub fn hash_tar<P: AsRef<Path>>(path: P) -> std::io::Result<Archive> {
+    let mut archive = Archive::new(&path, None, None)?;
+
+    let mut reader = BufReader::new(std::fs::File::open(&path)?);
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        read_members(&mut archive, GzDecoder::new(reader))?;
+    } else {
+        read_members(&mut archive, reader)?;
+    }
+
+    Ok(archive)
+}
+
+// read_members hashes every regular file of the tar stream into archive, keyed by its path in the archive
+fn read_members<R: Read>(archive: &mut Archive, reader: R) -> std::io::Result<()> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let member = entry.path()?.into_owned();
+        if !entry.header().entry_type().is_file() {
+            debug!("skipping {:?} entry {}", entry.header().entry_type(), member.display());
+            continue;
+        }
+        let name = match crate::archive_tree::name_of(&member) {
+            Some(name) => name,
+            None => {
+                warn!("skipping entry {} with no file name", member.display());
+                continue;
+            }
+        };
+
+        // members are hashed a chunk at a time as they are read, so a huge member takes no more memory than a small one
+        let (sha256, size) = crate::digest::sha256_of(&mut entry)?;
+        archive.files.insert(member, File { name, size, sha256 });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+
+    // MaxRead passes reads through, recording the largest read asked of it
+    struct MaxRead<'a, R> {
+        reader: R,
+        max: &'a std::cell::Cell<usize>,
+    }
+
+    impl<R: Read> Read for MaxRead<'_, R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.max.set(self.max.get().max(buf.len()));
+            self.reader.read(buf)
+        }
+    }
+
+    #[test]
+    fn large_member_is_read_in_chunks() {
+        use sha2::{Sha256, Digest};
+
+        // a tar holding a single 32 MiB member of zeros, generated as it is read rather than held in memory
+        const SIZE: u64 = 32 * 1024 * 1024;
+        let mut header = tar::Header::new_ustar();
+        header.set_path("large.bin").unwrap();
+        header.set_size(SIZE);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        let max = std::cell::Cell::new(0);
+        let stream = header.as_bytes().chain(MaxRead { reader: std::io::repeat(0).take(SIZE), max: &max }).chain(&[0u8; 1024][..]);
+
+        let mut archive = Archive::new("large.tar", Some(SIZE + 2048), Some([0; 32])).expect("recording archive");
+        read_members(&mut archive, stream).expect("hashing members");
+
+        let large = &archive.files[Path::new("large.bin")];
+        assert_eq!(large.size, SIZE);
+        let mut expected = Sha256::new();
+        std::io::copy(&mut std::io::repeat(0).take(SIZE), &mut expected).unwrap();
+        assert_eq!(large.sha256, <[u8; 32]>::from(expected.finalize()));
+        // buffering the whole member would have asked for ever larger reads
+        assert!(max.get() <= crate::digest::DEFAULT_BUFFER_SIZE, "read {} bytes at once", max.get());
+    }
+
+    #[test]
+    fn flat_archive_members() {
+        let archive = hash_tar("test_data/flat_archive.tar.gz").expect("hashing tar.gz");
+        assert_eq!(archive.files.len(), 3);
+
+        let mut hasher = FVC2Hasher::new();
+        for file in archive.files.values() {
+            hasher.read_sha256(file.sha256);
+        }
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+
+        // the same members are found without the gzip layer
+        let tmp = tempdir::TempDir::new("fvc_tar_stream").expect("creating temporary directory");
+        let plain = tmp.path().join("flat_archive.tar");
+        let mut decoder = GzDecoder::new(std::fs::File::open("test_data/flat_archive.tar.gz").unwrap());
+        std::io::copy(&mut decoder, &mut std::fs::File::create(&plain).unwrap()).expect("decompressing archive");
+        assert_eq!(hash_tar(&plain).expect("hashing tar").files, archive.files);
+
+        // and match what libarchive extracts
+        #[cfg(feature = "extract")]
+        {
+            let extracted = tmp.path().join("extracted");
+            crate::extract::extract_archive("test_data/flat_archive.tar.gz", &extracted).expect("extracting archive");
+            for (member, file) in archive.files.iter() {
+                let on_disk = File::new(extracted.join(member), None, None).expect("hashing extracted file");
+                assert_eq!(&on_disk, file, "{}", member.display());
+            }
+        }
+    }
+}
@@ -1,61 +1,373 @@
 use super::{FVCHasher, FVCSha256Hasher};
 
 use sha2::{Sha256, Digest};
+use sha2::digest::Output;
 use hex::ToHex;
 use std::io::Read;
 
-/// FVC2Hasher implements File Verification Code version 2
-pub struct FVC2Hasher {
-    // sha256s stores the calculated sha256s until ready to calculate the file verification code
-    sha256s: Vec<[u8; 32]>,
+/// FVCGenericHasher implements the construction of File Verification Code version 2 over any digest D:
+/// every member is hashed with D, and the code is the prefix of D followed by D of the sorted member digests
+/// With sha256 this is FVC2 itself, see FVC2Hasher. Other digests, such as sha512, give codes with their own prefix, only comparable to codes calculated with the same digest
+/// A clone holds the same members, so a hasher of common files can be cloned once per variant rather than reading the common files again
+#[derive(Clone)]
+pub struct FVCGenericHasher<D: CodeDigest> {
+    // members stores the digests of everything read until ready to calculate the file verification code
+    members: Vec<Output<D>>,
     // prevents re-sorting if sum or hex are called back-to-back
     sorted: bool,
+    // the number of members and their digest at the last current_hex, members are only ever added so the digest holds while the number is unchanged
+    current: Option<(usize, Output<D>)>,
+    // counts identical members once in the final digest, see with_dedup
+    dedup: bool,
 }
 
-impl FVC2Hasher {
-    /// create a new FVC2Hasher
+/// FVC2Hasher implements File Verification Code version 2
+pub type FVC2Hasher = FVCGenericHasher<Sha256>;
+
+/// CodeDigest is a digest FVCGenericHasher calculates codes with, and the prefix starting those codes
+/// Only sha256 has the `FVC2` prefix, so a code calculated with another digest is never taken for an FVC2 code
+pub trait CodeDigest: Digest {
+    /// PREFIX starts every code calculated with this digest, ahead of the final digest
+    const PREFIX: &'static [u8];
+}
+
+impl CodeDigest for Sha256 {
+    const PREFIX: &'static [u8] = &VERSION_PREFIX;
+}
+
+impl CodeDigest for sha2::Sha512 {
+    const PREFIX: &'static [u8] = b"FVC2-SHA512\0";
+}
+
+impl<D: CodeDigest> FVCGenericHasher<D> {
+    /// create a new FVCGenericHasher
     pub fn new() -> Self {
-        FVC2Hasher{ sha256s: Vec::new(), sorted: false}
+        FVCGenericHasher { members: Vec::new(), sorted: false, current: None, dedup: false }
     }
-}
 
-/// Implements FVCHasher for file verification code 2
-impl FVCHasher for FVC2Hasher {
-    fn read(&mut self, mut reader: impl Read) -> std::result::Result<usize, std::io::Error> {
-        // calculate and store sha256 of reader
-        let mut hasher = Sha256::new();
-        let mut buf = Vec::new();
-        match reader.read_to_end(&mut buf) {
-            Ok(size) => {
-                hasher.update(buf);
-                self.sha256s.push(hasher.finalize().into());
-
-                self.sorted = false; // sha256s changed and is no longer necessarily sorted
-                Ok(size)
+    /// with_dedup makes the code count identical members once, so a file copied within a directory gives the same code as the file alone
+    /// By default every member counts, and a duplicated file changes the code. Codes with and without dedup are not comparable,
+    /// and a hasher loaded with load_state counts every member again
+    pub fn with_dedup(mut self) -> Self {
+        self.dedup = true;
+        self.current = None;
+        self
+    }
+
+    /// output_len returns the length in bytes of the digest of every member, and of the digest at the end of the code
+    pub fn output_len() -> usize {
+        <D as Digest>::output_size()
+    }
+
+    /// code_len returns the length in bytes of the code returned by sum, the prefix of D followed by the final digest
+    pub fn code_len() -> usize {
+        D::PREFIX.len() + Self::output_len()
+    }
+
+    /// final_digest calculates the digest of the currently held, sorted, member digests
+    /// This is the file verification code without its version prefix
+    pub fn final_digest(&mut self) -> Output<D> {
+        if !self.sorted {
+            // sort members if necessary
+            self.members.sort();
+            self.sorted = true;
+        }
+
+        // calculate digest of sorted members, identical members being adjacent once sorted
+        let mut hasher = D::new();
+        for (i, member) in self.members.iter().enumerate() {
+            if self.dedup && i > 0 && self.members[i - 1] == *member {
+                continue;
             }
-            Err(e) => Err(e)
+            hasher.update(member);
         }
+
+        hasher.finalize()
     }
 
-    fn sum(&mut self) -> Vec<u8> {
+    /// current_hex returns the hex code of the members read so far, for showing a running code while files are still being read
+    /// The digest is kept until more members are read, so repeated calls are free, and the sort after a few more members only has to merge them
+    /// into the members already sorted, the sort being adaptive to the sorted run they are appended to
+    pub fn current_hex(&mut self) -> String {
+        let digest = match &self.current {
+            Some((len, digest)) if *len == self.members.len() => digest.clone(),
+            _ => {
+                let digest = self.final_digest();
+                self.current = Some((self.members.len(), digest.clone()));
+                digest
+            }
+        };
+
+        code_of(D::PREFIX, &digest).encode_hex::<String>()
+    }
+
+    /// verify returns whether the code of the members read so far is expected, a hex code as printed by fvc
+    /// Hex digits are compared case-insensitively, and leading whitespace and the `FVC:` label fvc prints before the code are ignored
+    pub fn verify(&mut self, expected: &str) -> bool {
+        let expected = expected.trim_start();
+        let expected = expected.strip_prefix("FVC:").unwrap_or(expected).trim();
+
+        self.hex().eq_ignore_ascii_case(expected)
+    }
+
+    /// len returns the number of members, files and anything else read, that are part of the code
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// is_empty returns whether nothing was read yet
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// unique_len returns the number of distinct members, members read more than once, like files with the same content, counting once
+    pub fn unique_len(&mut self) -> usize {
+        if !self.sorted {
+            // sort members if necessary, duplicates are then adjacent
+            self.members.sort();
+            self.sorted = true;
+        }
+
+        let duplicates = self.members.windows(2).filter(|pair| pair[0] == pair[1]).count();
+        self.members.len() - duplicates
+    }
+
+    /// extend_from adds every member of other, so codes of separately hashed inputs can be combined into the code of all of them
+    pub fn extend_from(&mut self, other: &FVCGenericHasher<D>) {
+        self.members.extend_from_slice(&other.members);
+        self.sorted = false; // members changed and are no longer necessarily sorted
+    }
+
+    /// merge acts like extend_from, but takes other, such as a hasher of a subtree handed back by a worker thread, and moves its members rather than copying them
+    pub fn merge(&mut self, mut other: FVCGenericHasher<D>) {
+        self.members.append(&mut other.members);
+        self.sorted = false; // members changed and are no longer necessarily sorted
+    }
+
+    /// save_state serializes the members read so far, so a long calculation can be checkpointed and resumed with load_state
+    /// The state is a byte for whether the members are sorted, followed by every member digest
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(1 + self.members.len() * Self::output_len());
+        state.push(self.sorted as u8);
+        for member in self.members.iter() {
+            state.extend_from_slice(member);
+        }
+
+        state
+    }
+
+    /// load_state creates a hasher holding the members of a state written by save_state
+    pub fn load_state(state: &[u8]) -> std::io::Result<Self> {
+        let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid FVC2 state: {}", reason));
+        let (sorted, members) = match state.split_first() {
+            Some((0, members)) => (false, members),
+            Some((1, members)) => (true, members),
+            Some(_) => return Err(invalid("unknown sorted flag")),
+            None => return Err(invalid("empty"))
+        };
+        if members.len() % Self::output_len() != 0 {
+            return Err(invalid("not a whole number of digests"));
+        }
+
+        let members = members.chunks_exact(Self::output_len()).map(Output::<D>::clone_from_slice).collect();
+        Ok(FVCGenericHasher { members, sorted, current: None, dedup: false })
+    }
+
+    /// export_hashes_binary returns the sorted member digests, concatenated without any header, for compact storage or transport of the member set
+    /// Unlike save_state, the blob holds nothing but the digests, so it is the same for the same members however they were read
+    pub fn export_hashes_binary(&mut self) -> Vec<u8> {
         if !self.sorted {
-            // sort sha256s if necessary
-            self.sha256s.sort();
+            self.members.sort();
             self.sorted = true;
         }
 
-        // calculate sha256 of sorted sha256s
+        self.members.concat()
+    }
+
+    /// import_hashes_binary creates a hasher holding the member digests of a blob written by export_hashes_binary
+    pub fn import_hashes_binary(blob: &[u8]) -> std::io::Result<Self> {
+        let digests = blob.chunks_exact(Self::output_len());
+        if !digests.remainder().is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid hashes blob: not a whole number of digests"));
+        }
+
+        let members: Vec<Output<D>> = digests.map(Output::<D>::clone_from_slice).collect();
+        let sorted = members.windows(2).all(|pair| pair[0] <= pair[1]);
+        Ok(FVCGenericHasher { members, sorted, current: None, dedup: false })
+    }
+
+    /// read_slice calculates and stores the digest of an in-memory region, such as a member located by offset inside a memory-mapped container
+    /// The slice is hashed in place, so no copy of the data is made
+    pub fn read_slice(&mut self, data: &[u8]) {
+        self.read_digest(D::digest(data));
+    }
+
+    /// read_digest takes the digest of a member directly, like read_sha256 does for FVC2Hasher
+    pub fn read_digest(&mut self, digest: Output<D>) {
+        self.members.push(digest);
+        self.sorted = false; // members changed and are no longer necessarily sorted
+    }
+}
+
+impl FVC2Hasher {
+    /// digest calculates the sha256 of the currently held, sorted, sha256s
+    /// This is the file verification code without its version prefix, as a fixed-size array
+    pub fn digest(&mut self) -> [u8; 32] {
+        self.final_digest().into()
+    }
+
+    /// read_namespace adds a member derived from namespace, scoping the resulting code to that namespace
+    /// This is not a plain FVC2 code: the same files give a different code in every namespace, and the same code for the same namespace,
+    /// so codes from different tenants or contexts cannot collide
+    pub fn read_namespace(&mut self, namespace: &str) {
+        self.read_salt(b"namespace", namespace);
+    }
+
+    /// read_build_id adds a member derived from a build or version identifier, so identical files stamped with different builds give different codes
+    /// Like a namespace, this gives a variant of FVC2 rather than a plain FVC2 code
+    pub fn read_build_id(&mut self, build_id: &str) {
+        self.read_salt(b"build-id", build_id);
+    }
+
+    /// read_metadata adds a member derived from a file's path, size and modification time instead of its content
+    /// Codes made of such members are not FVC2 codes, but a cheap check for whether anything may have changed
+    pub fn read_metadata(&mut self, relative_path: &std::path::Path, size: u64, modified: std::time::SystemTime) {
+        let since_epoch = modified.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+
         let mut hasher = Sha256::new();
-        for sha256 in self.sha256s.iter() {
-            hasher.update(sha256);
+        hasher.update(b"fvc-metadata");
+        hasher.update([0]);
+        hasher.update(relative_path.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(size.to_be_bytes());
+        hasher.update(since_epoch.as_secs().to_be_bytes());
+        hasher.update(since_epoch.subsec_nanos().to_be_bytes());
+        self.read_sha256(hasher.finalize().into());
+    }
+
+    // read_salt adds the sha256 of a domain-separated value as a member
+    fn read_salt(&mut self, domain: &[u8], value: &str) {
+        let mut hasher = Sha256::new();
+        hasher.update(b"fvc-");
+        hasher.update(domain);
+        hasher.update([0]);
+        hasher.update(value.as_bytes());
+        self.read_sha256(hasher.finalize().into());
+    }
+}
+
+/// fvc_of_entries calculates the hex FVC2 code of in-memory files, given as (path, content) pairs, without touching the filesystem
+/// As with files on disk, only the contents are part of the code, the paths only name the entries
+pub fn fvc_of_entries(entries: &[(std::path::PathBuf, Vec<u8>)]) -> String {
+    let mut hasher = FVC2Hasher::new();
+    for (_, content) in entries {
+        hasher.read_slice(content);
+    }
+
+    hasher.hex()
+}
+
+/// fvc_from_paths calculates the hex FVC2 code of the given files, for callers that walk the filesystem themselves
+/// Every path is hashed as a file as it is: directories are not expanded and archives are not extracted
+pub fn fvc_from_paths(paths: impl Iterator<Item = std::path::PathBuf>) -> std::io::Result<String> {
+    let mut hasher = FVC2Hasher::new();
+    for path in paths {
+        hasher.read_file(&path)?;
+    }
+
+    Ok(hasher.hex())
+}
+
+// VERSION_PREFIX starts every code, ahead of the final digest
+const VERSION_PREFIX: [u8; 5] = [b'F', b'V', b'C', b'2', 0];
+
+/// FVC2_CODE_LEN is the length in bytes of an FVC2 code, twice that in hex
+pub const FVC2_CODE_LEN: usize = VERSION_PREFIX.len() + 32;
+
+/// fvc_code_len returns the length in bytes of a code of the given version, or None for versions this library does not know
+pub fn fvc_code_len(version: u8) -> Option<usize> {
+    match version {
+        2 => Some(FVC2_CODE_LEN),
+        _ => None
+    }
+}
+
+/// VersionFold calculates the code of one FVC version from the member sha256s read into an FVC2Hasher
+pub type VersionFold = fn(&mut FVC2Hasher) -> Vec<u8>;
+
+/// SUPPORTED_VERSIONS lists every FVC version this library calculates, with how to fold the member sha256s into its code
+/// Every version shares the member sha256s, so a single pass over the files gives the codes of all of them
+pub const SUPPORTED_VERSIONS: &[(u8, VersionFold)] = &[(2, <FVC2Hasher as FVCHasher>::sum)];
+
+/// FvcParseError is why parse_fvc could not parse a code
+#[derive(Debug, PartialEq)]
+pub enum FvcParseError {
+    /// the code is not hex
+    InvalidHex(hex::FromHexError),
+    /// the code does not start with `FVC`, a version digit and a NUL byte
+    BadMagic,
+    /// the code is of a version this library does not know
+    UnknownVersion(u8),
+    /// the code is not as long as codes of its version are, both in bytes
+    WrongLength { expected: usize, actual: usize },
+}
+
+impl std::fmt::Display for FvcParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FvcParseError::InvalidHex(err) => write!(f, "invalid file verification code: {}", err),
+            FvcParseError::BadMagic => write!(f, "invalid file verification code: missing FVC version prefix"),
+            FvcParseError::UnknownVersion(version) => write!(f, "invalid file verification code: unknown version FVC{}", version),
+            FvcParseError::WrongLength { expected, actual } => write!(f, "invalid file verification code: expected {} bytes, found {}", expected, actual),
         }
+    }
+}
+
+impl std::error::Error for FvcParseError {}
 
-        // prepend version to final sha256
-        let hash: [u8; 32] = hasher.finalize().into();
-        let mut code = vec![b'F', b'V', b'C', b'2', 0];
-        code.extend_from_slice(&hash[..]);
+/// parse_fvc decodes a hex code, such as one printed by fvc, into its version and final digest
+/// The version prefix is checked, and the code must be as long as codes of its version are
+pub fn parse_fvc(code: &str) -> Result<(u8, [u8; 32]), FvcParseError> {
+    let code = hex::decode(code.trim()).map_err(FvcParseError::InvalidHex)?;
+    let version = match code.get(..VERSION_PREFIX.len()) {
+        Some([b'F', b'V', b'C', version, 0]) if version.is_ascii_digit() => version - b'0',
+        _ => return Err(FvcParseError::BadMagic)
+    };
+    match fvc_code_len(version) {
+        Some(len) if len == code.len() => {
+            let mut digest = [0u8; 32];
+            digest.copy_from_slice(&code[VERSION_PREFIX.len()..]);
+            Ok((version, digest))
+        },
+        Some(len) => Err(FvcParseError::WrongLength { expected: len, actual: code.len() }),
+        None => Err(FvcParseError::UnknownVersion(version))
+    }
+}
+
+// code_of prepends the prefix to the final digest
+fn code_of(prefix: &[u8], digest: &[u8]) -> Vec<u8> {
+    let mut code = Vec::with_capacity(prefix.len() + digest.len());
+    code.extend_from_slice(prefix);
+    code.extend_from_slice(digest);
+
+    code
+}
+
+/// Implements FVCHasher for file verification code 2, over any digest
+impl<D: CodeDigest> FVCHasher for FVCGenericHasher<D> {
+    fn read(&mut self, reader: impl Read) -> std::result::Result<usize, std::io::Error> {
+        // calculate and store the digest of reader, a fixed-size chunk at a time so memory use does not grow with the size of what is read
+        match crate::digest::digest_of_buffered::<D, _>(reader, crate::digest::DEFAULT_BUFFER_SIZE) {
+            Ok((digest, size)) => {
+                self.read_digest(digest);
+                Ok(size as usize)
+            }
+            Err(e) => Err(e)
+        }
+    }
 
-        code
+    fn sum(&mut self) -> Vec<u8> {
+        code_of(D::PREFIX, &self.final_digest())
     }
     fn hex(&mut self) -> String {
         // encode sum as hex string
@@ -89,9 +401,44 @@ impl FVCSha256Hasher for FVC2Hasher {
     /// assert_eq!(result, "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
     /// ```
     fn read_sha256(&mut self, sha256: [u8; 32]) {
-        // push sha256 directly and acknowledge vector is no longer sorted
-        self.sha256s.push(sha256);
-        self.sorted = false;
+        // push sha256 directly, read_digest acknowledges members are no longer sorted
+        self.read_digest(sha256.into());
+    }
+}
+
+/// Collects precomputed sha256s, such as ones read from a manifest or database, into an FVC2Hasher
+///
+/// # Examples
+///
+/// ```
+/// use file_verification_code::FVCHasher;
+/// use file_verification_code::FVC2Hasher;
+/// use hex_literal::hex;
+/// let foo_sha256 = hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c");
+/// let bar_sha256 = hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730");
+/// let zap_sha256 = hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b");
+///
+/// let sha256s = [foo_sha256, bar_sha256, zap_sha256];
+///
+/// let mut hasher: FVC2Hasher = sha256s.into_iter().collect();
+///
+/// let result = hasher.hex();
+/// assert_eq!(result, "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+/// ```
+impl FromIterator<[u8; 32]> for FVC2Hasher {
+    fn from_iter<I: IntoIterator<Item = [u8; 32]>>(sha256s: I) -> Self {
+        let mut hasher = FVC2Hasher::new();
+        hasher.extend(sha256s);
+
+        hasher
+    }
+}
+
+/// Adds precomputed sha256s like read_sha256
+impl Extend<[u8; 32]> for FVC2Hasher {
+    fn extend<I: IntoIterator<Item = [u8; 32]>>(&mut self, sha256s: I) {
+        self.members.extend(sha256s.into_iter().map(Output::<Sha256>::from));
+        self.sorted = false; // members changed and are no longer necessarily sorted
     }
 }
 
@@ -116,4 +463,330 @@ mod tests {
         let result = hasher.hex();
         assert_eq!(result, "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
     }
+
+    #[test]
+    fn fvc2_digest_is_sum_without_prefix() {
+        let mut hasher = FVC2Hasher::new();
+        hasher.read_slice(b"foo\n");
+        hasher.read_slice(b"bar\n");
+
+        let digest = hasher.digest();
+        let sum = hasher.sum();
+        assert_eq!(&sum[sum.len() - 32..], &digest[..]);
+    }
+
+    #[test]
+    fn fvc2_namespaces_scope_codes() {
+        let code = |namespace: Option<&str>| {
+            let mut hasher = FVC2Hasher::new();
+            if let Some(namespace) = namespace {
+                hasher.read_namespace(namespace);
+            }
+            hasher.read_slice(b"foo\n");
+            hasher.read_slice(b"bar\n");
+            hasher.hex()
+        };
+
+        assert_eq!(code(Some("tenant-a")), code(Some("tenant-a")));
+        assert_ne!(code(Some("tenant-a")), code(Some("tenant-b")));
+        assert_ne!(code(Some("tenant-a")), code(None));
+    }
+
+    #[test]
+    fn fvc2_build_ids_differ() {
+        let code = |build_id: &str| {
+            let mut hasher = FVC2Hasher::new();
+            hasher.read_build_id(build_id);
+            hasher.read_slice(b"foo\n");
+            hasher.read_slice(b"bar\n");
+            hasher.hex()
+        };
+
+        assert_eq!(code("1.2.3"), code("1.2.3"));
+        assert_ne!(code("1.2.3"), code("1.2.4"));
+
+        // a build id is not the namespace of the same name
+        let mut namespaced = FVC2Hasher::new();
+        namespaced.read_namespace("1.2.3");
+        namespaced.read_slice(b"foo\n");
+        namespaced.read_slice(b"bar\n");
+        assert_ne!(code("1.2.3"), namespaced.hex());
+    }
+
+    #[test]
+    fn fvc2_read_slice_of_region() {
+        let container = b"headerfoo\nbar\nzap\ntrailer";
+        let region = &container[6..10]; // "foo\n"
+
+        let mut sliced = FVC2Hasher::new();
+        sliced.read_slice(region);
+
+        let mut direct = FVC2Hasher::new();
+        direct.read_sha256(Sha256::digest(b"foo\n").into());
+
+        assert_eq!(sliced.members, vec![Output::<Sha256>::from(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"))]);
+        assert_eq!(sliced.hex(), direct.hex());
+    }
+
+    #[test]
+    fn fvc2_of_in_memory_entries() {
+        let entries = [
+            (std::path::PathBuf::from("foo.txt"), b"foo\n".to_vec()),
+            (std::path::PathBuf::from("bar.txt"), b"bar\n".to_vec()),
+            (std::path::PathBuf::from("A/zap.txt"), b"zap\n".to_vec()),
+        ];
+
+        assert_eq!(fvc_of_entries(&entries), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+    }
+
+    #[test]
+    fn fvc_of_given_paths() {
+        let flat_files = std::path::Path::new("test_data/flat_files");
+        let paths = ["foo.txt", "bar.txt", "zap.txt"].iter().map(|name| flat_files.join(name));
+        assert_eq!(fvc_from_paths(paths).expect("hashing paths"), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+
+        // directories are not expanded
+        assert!(fvc_from_paths(std::iter::once(flat_files.to_path_buf())).is_err());
+    }
+
+    #[test]
+    fn len_counts_members_without_sorting() {
+        let mut hasher = FVC2Hasher::new();
+        assert!(hasher.is_empty());
+        assert_eq!(hasher.len(), 0);
+
+        hasher.read_slice(b"zap\n");
+        hasher.read_slice(b"foo\n");
+        hasher.read_slice(b"foo\n");
+        assert!(!hasher.is_empty());
+        assert_eq!(hasher.len(), 3);
+        // counting leaves the members in the order they were read
+        assert!(!hasher.sorted);
+        assert_eq!(hasher.members[0], Sha256::digest(b"zap\n"));
+    }
+
+    #[test]
+    fn read_large_file_in_chunks() {
+        // several megabytes, not a multiple of the buffer size, with every chunk differing
+        let content: Vec<u8> = (0..5 * 1024 * 1024 + 123).map(|index: u32| (index % 251) as u8).collect();
+        let tmp = tempdir::TempDir::new("fvc_read_chunks").expect("creating temporary directory");
+        let path = tmp.path().join("large.bin");
+        std::fs::write(&path, &content).expect("writing test file");
+
+        let mut streamed = FVC2Hasher::new();
+        assert_eq!(streamed.read(std::fs::File::open(&path).unwrap()).expect("reading file"), content.len());
+        let mut whole = FVC2Hasher::new();
+        whole.read_sha256(Sha256::digest(&content).into());
+        assert_eq!(streamed.hex(), whole.hex());
+    }
+
+    #[test]
+    fn fvc2_resume_from_state() {
+        let mut saved = FVC2Hasher::new();
+        saved.read_slice(b"foo\n");
+        saved.read_slice(b"bar\n");
+        let state = saved.save_state();
+        assert_eq!(state.len(), 1 + 2 * 32);
+
+        let mut resumed = FVC2Hasher::load_state(&state).expect("loading state");
+        resumed.read_slice(b"zap\n");
+        assert_eq!(resumed.hex(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+
+        // the sorted flag survives, and a truncated state is rejected
+        let sorted = resumed.save_state();
+        assert_eq!(sorted[0], 1);
+        assert_eq!(FVC2Hasher::load_state(&sorted).unwrap().hex(), resumed.hex());
+        assert!(FVC2Hasher::load_state(&state[..state.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn hashes_binary_round_trip() {
+        let mut exported = FVC2Hasher::new();
+        for content in [b"zap\n", b"foo\n", b"bar\n"] {
+            exported.read_slice(content);
+        }
+        let blob = exported.export_hashes_binary();
+        assert_eq!(blob.len(), 3 * 32);
+        assert!(blob.chunks_exact(32).zip(blob.chunks_exact(32).skip(1)).all(|(a, b)| a < b));
+
+        let mut imported = FVC2Hasher::import_hashes_binary(&blob).expect("importing hashes");
+        assert_eq!(imported.len(), 3);
+        assert_eq!(imported.hex(), exported.hex());
+        assert_eq!(imported.hex(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+        assert!(FVC2Hasher::import_hashes_binary(&blob[..blob.len() - 1]).is_err());
+        assert!(FVC2Hasher::import_hashes_binary(&[]).expect("importing no hashes").is_empty());
+    }
+
+    #[test]
+    fn extend_combines_codes() {
+        let mut foo_bar = FVC2Hasher::new();
+        foo_bar.read_slice(b"foo\n");
+        foo_bar.read_slice(b"bar\n");
+        let mut zap = FVC2Hasher::new();
+        zap.read_slice(b"zap\n");
+
+        foo_bar.extend_from(&zap);
+        assert_eq!(foo_bar.hex(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+    }
+
+    #[test]
+    fn extend_with_sha256s() {
+        let mut hasher = FVC2Hasher::new();
+        hasher.read_sha256(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"));
+        hasher.extend([
+            hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730"),
+            hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b"),
+        ]);
+        assert_eq!(hasher.len(), 3);
+        assert_eq!(hasher.hex(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+    }
+
+    #[test]
+    fn merge_equals_single_hasher() {
+        let sha256s = [
+            hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"),
+            hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730"),
+            hex!("a121b45bde6824e7ffd72c814e545a35e13b687680ea4e62a4a4405ab23acb0b"),
+        ];
+        let mut single = FVC2Hasher::new();
+        for sha256 in sha256s.iter() {
+            single.read_sha256(*sha256);
+        }
+
+        let (mut first, mut second) = (FVC2Hasher::new(), FVC2Hasher::new());
+        first.read_sha256(sha256s[2]);
+        // a sorted hasher must not stay marked sorted once merged into
+        first.hex();
+        second.read_sha256(sha256s[0]);
+        second.read_sha256(sha256s[1]);
+        first.merge(second);
+        assert_eq!(first.len(), 3);
+        assert_eq!(first.hex(), single.hex());
+    }
+
+    #[test]
+    fn dedup_counts_identical_members_once() {
+        let foo_sha256 = hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c");
+        let bar_sha256 = hex!("7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730");
+        let mut once = FVC2Hasher::new();
+        once.read_sha256(foo_sha256);
+        once.read_sha256(bar_sha256);
+
+        let (mut counted, mut deduped) = (FVC2Hasher::new(), FVC2Hasher::new().with_dedup());
+        for sha256 in [foo_sha256, bar_sha256, foo_sha256] {
+            counted.read_sha256(sha256);
+            deduped.read_sha256(sha256);
+        }
+
+        // by default the duplicate changes the code
+        assert_ne!(counted.hex(), once.hex());
+        assert_eq!(deduped.hex(), once.hex());
+        assert_eq!(deduped.len(), 3);
+        assert_eq!(deduped.unique_len(), 2);
+    }
+
+    #[test]
+    fn cloned_hasher_extends_independently() {
+        let mut base = FVC2Hasher::new();
+        base.read_slice(b"foo\n");
+        base.read_slice(b"bar\n");
+        base.hex(); // the clone must carry over the sorted flag along with the members
+
+        let mut variant = base.clone();
+        variant.read_slice(b"zap\n");
+        let mut fresh = FVC2Hasher::new();
+        for content in [&b"foo\n"[..], b"bar\n", b"zap\n"] {
+            fresh.read_slice(content);
+        }
+        assert_eq!(variant.hex(), fresh.hex());
+
+        // the base is unchanged by its clone
+        assert_eq!(base.len(), 2);
+        assert_eq!(base.hex(), fvc_of_entries(&[(std::path::PathBuf::from("foo"), b"foo\n".to_vec()), (std::path::PathBuf::from("bar"), b"bar\n".to_vec())]));
+    }
+
+    #[test]
+    fn current_hex_follows_reads() {
+        let mut running = FVC2Hasher::new();
+        let mut read: Vec<&[u8]> = Vec::new();
+        for content in [&b"zap\n"[..], b"foo\n", b"bar\n", b"foo\n"] {
+            running.read_slice(content);
+            read.push(content);
+
+            let mut fresh = FVC2Hasher::new();
+            for content in read.iter() {
+                fresh.read_slice(content);
+            }
+            assert_eq!(running.current_hex(), fresh.hex());
+            assert_eq!(running.current_hex(), fresh.hex()); // unchanged without new members
+        }
+    }
+
+    #[test]
+    fn code_len_matches_sum() {
+        let mut hasher = FVC2Hasher::new();
+        hasher.read_slice(b"foo\n");
+        assert_eq!(hasher.sum().len(), FVC2_CODE_LEN);
+        assert_eq!(fvc_code_len(2), Some(37));
+        assert_eq!(fvc_code_len(1), None);
+    }
+
+    #[test]
+    fn supported_versions_match_their_code_len() {
+        let mut hasher = FVC2Hasher::new();
+        hasher.read_slice(b"foo\n");
+        for (version, fold) in SUPPORTED_VERSIONS {
+            let code = fold(&mut hasher);
+            assert_eq!(Some(code.len()), fvc_code_len(*version));
+            assert_eq!(parse_fvc(&hex::encode(&code)).map(|(parsed, _)| parsed), Ok(*version));
+        }
+    }
+
+    #[test]
+    fn parse_fvc_returns_version_and_digest() {
+        let mut hasher = FVC2Hasher::new();
+        hasher.read_slice(b"foo\n");
+        assert_eq!(parse_fvc(&hasher.hex()), Ok((2, hasher.digest())));
+        assert_eq!(parse_fvc(&hasher.hex().to_uppercase()), Ok((2, hasher.digest())));
+
+        assert_eq!(parse_fvc(&hasher.hex()[..FVC2_CODE_LEN * 2 - 2]), Err(FvcParseError::WrongLength { expected: 37, actual: 36 }));
+        assert_eq!(parse_fvc("4656433300ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374"), Err(FvcParseError::UnknownVersion(3)));
+        assert_eq!(parse_fvc("0056433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374"), Err(FvcParseError::BadMagic));
+        assert!(matches!(parse_fvc("not hex"), Err(FvcParseError::InvalidHex(_))));
+    }
+
+    #[test]
+    fn verify_against_expected_code() {
+        let mut hasher = FVC2Hasher::new();
+        for content in [&b"foo\n"[..], b"bar\n", b"zap\n"] {
+            hasher.read_slice(content);
+        }
+
+        assert!(hasher.verify("4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374"));
+        assert!(hasher.verify("4656433200AD460448A5947428E2C3E98ADFE45915D71F7A4B399910FED1022CC4E1CDC374"));
+        assert!(hasher.verify("  FVC: 4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374\n"));
+        assert!(!hasher.verify("465643320080906dab16c118543c5b8ce2f5a819ae1e690b992e04f5f61f73f1886a3037ba"));
+        assert!(!hasher.verify(""));
+    }
+
+    #[test]
+    fn generic_hasher_over_sha512() {
+        let mut hasher = FVCGenericHasher::<sha2::Sha512>::new();
+        for content in [&b"zap\n"[..], b"foo\n", b"bar\n"] {
+            hasher.read(content).unwrap();
+        }
+        let code = hasher.sum();
+        assert_eq!(FVCGenericHasher::<sha2::Sha512>::code_len(), 76);
+        assert_eq!(code.len(), FVCGenericHasher::<sha2::Sha512>::code_len());
+        assert_eq!(FVC2Hasher::new().sum().len(), FVC2Hasher::code_len());
+
+        let mut sha512s: Vec<_> = [&b"zap\n"[..], b"foo\n", b"bar\n"].iter().map(sha2::Sha512::digest).collect();
+        sha512s.sort();
+        let mut expected = b"FVC2-SHA512\0".to_vec();
+        expected.extend_from_slice(&sha2::Sha512::digest(sha512s.concat()));
+        assert_eq!(code, expected);
+
+        // a sha512 code is not taken for an FVC2 code
+        assert_eq!(parse_fvc(&hex::encode(&code)), Err(FvcParseError::BadMagic));
+    }
 }
@@ -0,0 +1,98 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! whole_stream calculates, along with a file verification code, the sha256 of the contents of all files concatenated, reading every file once
+
+use std::io::Read;
+use sha2::{Sha256, Digest};
+
+use crate::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+use crate::digest::sha256_of;
+
+// TeeReader passes reads through from its reader, hashing everything read into a shared sha256
+struct TeeReader<'a, R> {
+    reader: R,
+    stream: &'a mut Sha256,
+}
+
+impl<R: Read> Read for TeeReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.stream.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// WholeStreamHasher is an FVC2Hasher that also calculates the sha256 of everything it reads, concatenated in the order it was read
+/// The file verification code does not depend on the order files are read in, but the whole-stream sha256 does,
+/// so files should be read in a stable order, like the sorted order of a directory walk
+pub struct WholeStreamHasher {
+    fvc: FVC2Hasher,
+    stream: Sha256,
+}
+
+impl Default for WholeStreamHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WholeStreamHasher {
+    /// create a new WholeStreamHasher
+    pub fn new() -> Self {
+        WholeStreamHasher { fvc: FVC2Hasher::new(), stream: Sha256::new() }
+    }
+
+    /// stream_sha256 returns the sha256 of everything read so far, concatenated
+    pub fn stream_sha256(&self) -> [u8; 32] {
+        self.stream.clone().finalize().into()
+    }
+
+    /// stream_hex behaves like stream_sha256, except returns the sha256 as a hex string
+    pub fn stream_hex(&self) -> String {
+        hex::encode(self.stream_sha256())
+    }
+}
+
+/// Implements FVCHasher by hashing every reader once, into both its own sha256 and the whole stream's
+impl FVCHasher for WholeStreamHasher {
+    fn read(&mut self, reader: impl Read) -> std::result::Result<usize, std::io::Error> {
+        let (sha256, size) = sha256_of(TeeReader { reader, stream: &mut self.stream })?;
+        self.fvc.read_sha256(sha256);
+
+        Ok(size as usize)
+    }
+
+    fn sum(&mut self) -> Vec<u8> {
+        self.fvc.sum()
+    }
+
+    fn hex(&mut self) -> String {
+        self.fvc.hex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_sha256_is_of_concatenation() {
+        let mut hasher = WholeStreamHasher::new();
+        for content in [&b"foo\n"[..], &b"bar\n"[..], &b"zap\n"[..]] {
+            assert_eq!(hasher.read(content).expect("reading content"), 4);
+        }
+
+        assert_eq!(hasher.hex(), "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374");
+        let concatenation: [u8; 32] = Sha256::digest(b"foo\nbar\nzap\n").into();
+        assert_eq!(hasher.stream_sha256(), concatenation);
+        assert_eq!(hasher.stream_hex(), hex::encode(concatenation));
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! An audit trail of how the code was produced, as newline-delimited JSON records kept apart from the human-readable logs
+//! Every file is recorded with whether it was extracted, hashed, skipped or failed, and why
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+
+use crate::events::EventLog;
+
+/// Decision is what was done with a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// the path was extracted as an archive, and its contents processed
+    #[cfg_attr(not(feature = "extract"), allow(dead_code))]
+    Extracted,
+    /// the path was hashed as a file
+    Hashed,
+    /// the path was left out of the code
+    Skipped,
+    /// processing the path failed, failing the whole run
+    Failed,
+}
+
+/// Record is a single line of the audit log
+#[derive(Debug, Serialize)]
+pub struct Record<'a> {
+    pub path: &'a Path,
+    pub decision: Decision,
+    pub reason: &'a str,
+    /// seconds since the Unix epoch
+    pub timestamp: f64,
+}
+
+/// AuditLog writes records to a file or stdout, it can be cloned to share the same destination
+#[derive(Debug, Clone)]
+pub struct AuditLog(EventLog);
+
+impl AuditLog {
+    /// create writes records to the given file, or to stdout if the path is `-`
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        EventLog::create(path).map(AuditLog)
+    }
+
+    /// record writes what was decided for path, and why, stamped with the current time
+    pub fn record(&self, path: &Path, decision: Decision, reason: &str) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs_f64()).unwrap_or_default();
+        self.0.write(&Record { path, decision, reason, timestamp });
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Bundle every file that contributed to the code into a reproducible tar, as evidence that can be verified again later
+//! Archives are bundled as they are, rather than their extracted members, so processing the extracted bundle with the same options gives the same code
+
+use file_verification_code::archive_tree::Collection;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use log::*;
+
+/// write_bundle writes every file on disk that contributed to the given trees, processed from the given inputs, to a tar at path
+/// Every input is named `<n>/<name>`, n being its position among the inputs, with a directory's files under it, and entries are sorted by name with their metadata zeroed,
+/// so the same files always give the same bundle
+pub fn write_bundle(path: &Path, inputs: &[PathBuf], collections: &[Collection]) -> std::io::Result<()> {
+    let mut builder = tar::Builder::new(std::io::BufWriter::new(std::fs::File::create(path)?));
+    for (name, source) in contributors(inputs, collections) {
+        debug!("bundling {} as {}", source.display(), name.display());
+        let file = std::fs::File::open(&source)?;
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(file.metadata()?.len());
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        builder.append_data(&mut header, &name, file)?;
+    }
+    builder.into_inner()?.into_inner().map_err(|err| err.into_error())?;
+
+    Ok(())
+}
+
+// contributors maps the name in the bundle of every file on disk, given or found by walking a given directory, to its path
+// Files found in extracted archives are not on disk anymore, the archive holding them is bundled instead
+fn contributors(inputs: &[PathBuf], collections: &[Collection]) -> BTreeMap<PathBuf, PathBuf> {
+    let mut files = BTreeMap::new();
+    for (index, (input, collection)) in inputs.iter().zip(collections).enumerate() {
+        let root = PathBuf::from((index + 1).to_string()).join(input.file_name().unwrap_or_default());
+
+        match collection {
+            Collection::File(_) | Collection::Archive(_) => {
+                files.insert(root, input.clone());
+            },
+            Collection::Directory(directory) => {
+                for path in directory.files.keys().chain(directory.archives.keys()) {
+                    let relative = path.strip_prefix(input).expect("walked paths are under their directory");
+                    files.insert(root.join(relative), path.clone());
+                }
+            },
+            Collection::Empty => ()
+        }
+    }
+
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{self, ProcessOptions, Processor};
+    use file_verification_code::{FVCHasher, FVC2Hasher};
+
+    #[test]
+    fn bundle_gives_same_fvc() {
+        let inputs = [PathBuf::from("test_data/flat_files"), PathBuf::from("test_data/nested_archive.zip"), PathBuf::from("test_data/foo.txt.gz")];
+        let processor = process::new(process::default_policy(), ProcessOptions::default());
+        let collections = processor.collect(&inputs).expect("processing inputs");
+        let mut hasher = FVC2Hasher::new();
+        processor.hash_collections(&mut hasher, &collections);
+
+        let tmp = tempdir::TempDir::new("fvc_bundle").expect("creating temporary directory");
+        let bundle = tmp.path().join("bundle.tar");
+        write_bundle(&bundle, &inputs, &collections).expect("writing bundle");
+        let again = tmp.path().join("again.tar");
+        write_bundle(&again, &inputs, &collections).expect("writing bundle");
+        assert_eq!(std::fs::read(&bundle).unwrap(), std::fs::read(&again).unwrap());
+
+        let extracted = tmp.path().join("extracted");
+        tar::Archive::new(std::fs::File::open(&bundle).unwrap()).unpack(&extracted).expect("extracting bundle");
+        assert!(extracted.join("1/flat_files/foo.txt").is_file());
+        assert!(extracted.join("2/nested_archive.zip").is_file());
+        let mut rehashed = FVC2Hasher::new();
+        processor.calculate_fvc(&mut rehashed, &[extracted]).expect("processing extracted bundle");
+        assert_eq!(rehashed.hex(), hasher.hex());
+    }
+}
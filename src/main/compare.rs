@@ -0,0 +1,156 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Compare two trees saved with `--tree`, without looking at the files they were made from
+//! Files are identified by their path, with the paths of the archives they were found in as prefixes
+
+use file_verification_code::archive_tree::{self, Collection};
+use file_verification_code::{FVC2Hasher, FVCHasher, FVCSha256Hasher};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// TreeDiff is the difference between an old and a new tree
+#[derive(Debug, Default, PartialEq)]
+pub struct TreeDiff {
+    /// files only in the new tree
+    pub added: Vec<PathBuf>,
+    /// files only in the old tree
+    pub removed: Vec<PathBuf>,
+    /// files in both trees, with different content
+    pub changed: Vec<PathBuf>,
+    pub old_fvc: String,
+    pub new_fvc: String
+}
+
+impl TreeDiff {
+    /// fvcs_match is whether both trees have the same file verification code
+    /// This can be the case even with differences, for example when files were only moved
+    pub fn fvcs_match(&self) -> bool {
+        self.old_fvc == self.new_fvc
+    }
+}
+
+impl std::fmt::Display for TreeDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for path in self.added.iter() {
+            writeln!(f, "+ {}", path.display())?;
+        }
+        for path in self.removed.iter() {
+            writeln!(f, "- {}", path.display())?;
+        }
+        for path in self.changed.iter() {
+            writeln!(f, "~ {}", path.display())?;
+        }
+        match self.fvcs_match() {
+            true => write!(f, "FVCs match: {}", self.old_fvc),
+            false => write!(f, "FVCs differ: {} != {}", self.old_fvc, self.new_fvc)
+        }
+    }
+}
+
+/// load_tree reads a tree written with `--tree`
+pub fn load_tree<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Collection>> {
+    let file = std::fs::File::open(path)?;
+    let tree = serde_json::from_reader(std::io::BufReader::new(file))?;
+
+    Ok(tree)
+}
+
+/// compare_trees lists the files added, removed and changed from old to new, and the file verification code of each
+pub fn compare_trees(old: &[Collection], new: &[Collection]) -> TreeDiff {
+    let (old_files, old_fvc) = flatten(old);
+    let (new_files, new_fvc) = flatten(new);
+
+    let mut diff = TreeDiff { old_fvc, new_fvc, ..Default::default() };
+    for (path, sha256) in new_files.iter() {
+        match old_files.get(path) {
+            None => diff.added.push(path.clone()),
+            Some(old_sha256) if old_sha256 != sha256 => diff.changed.push(path.clone()),
+            Some(_) => ()
+        }
+    }
+    diff.removed = old_files.keys().filter(|path| !new_files.contains_key(*path)).cloned().collect();
+
+    diff
+}
+
+// flatten returns the sha256 of every file in the tree by path, and the file verification code of the tree
+// Every input's files are under its own root, and an input with a path already taken by an earlier input, such as a file of the same name given from another directory,
+// is put under `#<n>`, n being its position among the inputs, so its files never overwrite another input's
+fn flatten(collections: &[Collection]) -> (BTreeMap<PathBuf, [u8; 32]>, String) {
+    let mut files = BTreeMap::new();
+    let mut hasher = FVC2Hasher::new();
+    for (index, collection) in collections.iter().enumerate() {
+        let mut input = archive_tree::contributions(collection);
+        for sha256 in input.values() {
+            hasher.read_sha256(*sha256);
+        }
+
+        if input.keys().any(|path| files.contains_key(path)) {
+            let namespace = PathBuf::from(format!("#{}", index + 1));
+            input = input.into_iter().map(|(path, sha256)| (namespace.join(path), sha256)).collect();
+        }
+        files.extend(input);
+    }
+
+    (files, hasher.hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use file_verification_code::archive_tree::{Directory, File};
+
+    // save writes a tree of a directory holding the given files, as `--tree` would
+    fn save(path: &Path, files: &[(&str, u8)]) {
+        let mut directory = Directory::new("root");
+        for (name, content) in files {
+            directory.files.insert(Path::new("root").join(name), File { name: name.to_string(), size: 1, sha256: [*content; 32] });
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&[Collection::Directory(directory)]).unwrap()).expect("saving tree");
+    }
+
+    #[test]
+    fn one_file_changed() {
+        let tmp = tempdir::TempDir::new("fvc_compare").expect("creating temporary directory");
+        let (old, new) = (tmp.path().join("old.json"), tmp.path().join("new.json"));
+        save(&old, &[("a.txt", 1), ("b.txt", 2)]);
+        save(&new, &[("a.txt", 1), ("b.txt", 3)]);
+
+        let diff = compare_trees(&load_tree(&old).expect("loading old tree"), &load_tree(&new).expect("loading new tree"));
+        assert_eq!(diff.changed, vec![PathBuf::from("root/b.txt")]);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert!(!diff.fvcs_match());
+
+        let same = compare_trees(&load_tree(&old).unwrap(), &load_tree(&old).unwrap());
+        assert_eq!(same, TreeDiff { old_fvc: diff.old_fvc.clone(), new_fvc: diff.old_fvc, ..Default::default() });
+    }
+
+    #[test]
+    fn same_path_in_two_inputs() {
+        let file = |name: &str, content: u8| Collection::File(File { name: name.to_string(), size: 1, sha256: [content; 32] });
+        let directory = |root: &str, content: u8| {
+            let mut directory = Directory::new(root);
+            directory.files.insert(Path::new(root).join("a.txt"), File { name: "a.txt".to_string(), size: 1, sha256: [content; 32] });
+            Collection::Directory(directory)
+        };
+
+        // a.txt from two directories, and a.txt given directly from two places
+        let old = [directory("one", 1), directory("two", 2), file("a.txt", 3), file("a.txt", 4)];
+        let (files, _) = flatten(&old);
+        assert_eq!(files.keys().cloned().collect::<Vec<_>>(), vec![PathBuf::from("#4/a.txt"), PathBuf::from("a.txt"), PathBuf::from("one/a.txt"), PathBuf::from("two/a.txt")]);
+
+        let new = [directory("one", 1), directory("two", 2), file("a.txt", 3), file("a.txt", 5)];
+        let diff = compare_trees(&old, &new);
+        assert_eq!(diff.changed, vec![PathBuf::from("#4/a.txt")]);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+    }
+}
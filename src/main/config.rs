@@ -0,0 +1,115 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Default options read from a TOML config file
+//! The file is the one given with `--config`, otherwise `.fvcrc` or `fvc.toml` in the current directory.
+//! Options given on the command line always override the config file.
+
+use crate::CLI;
+use crate::process::ExtractPolicy;
+
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use log::*;
+
+// config files looked for in the current directory, in order, when no --config is given
+const DEFAULT_FILES: &[&str] = &[".fvcrc", "fvc.toml"];
+
+/// Config holds the options that can be given defaults, named like their command line flags
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub extract: Option<ExtractPolicy>,
+    pub exclude_vcs: Option<bool>,
+}
+
+impl Config {
+    /// load reads the given config file, or the first default config file found in the current directory
+    /// Having no config file is not an error and results in an empty Config
+    pub fn load(path: Option<&Path>) -> std::io::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match DEFAULT_FILES.iter().map(PathBuf::from).find(|path| path.is_file()) {
+                Some(path) => path,
+                None => return Ok(Config::default())
+            }
+        };
+
+        debug!("reading config file {}", path.display());
+        let contents = std::fs::read_to_string(&path)?;
+        Config::parse(&contents).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))
+    }
+
+    /// parse reads a config from TOML
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// apply sets every option of the config on cli, unless it was explicitly given on the command line
+    pub fn apply(&self, cli: &mut CLI, matches: &ArgMatches) {
+        let from_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        if let Some(extract) = self.extract {
+            if !from_command_line("extract") {
+                cli.extract = extract;
+            }
+        }
+        if let Some(exclude_vcs) = self.exclude_vcs {
+            if !from_command_line("exclude_vcs") {
+                cli.exclude_vcs = exclude_vcs;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    // parse_with parses the given command line, and applies the given config to it
+    fn parse_with(config: &Config, args: &[&str]) -> CLI {
+        let matches = CLI::command().get_matches_from(args);
+        let mut cli = CLI::from_arg_matches(&matches).expect("parsing command line");
+        config.apply(&mut cli, &matches);
+        cli
+    }
+
+    #[test]
+    fn config_sets_defaults_and_flags_override() {
+        let config = Config::parse("extract = \"all\"\nexclude-vcs = true\n").expect("parsing config");
+        assert_eq!(config, Config { extract: Some(ExtractPolicy::All), exclude_vcs: Some(true) });
+
+        let cli = parse_with(&config, &["fvc", "test_data/flat_archive.tar.gz"]);
+        assert_eq!(cli.extract, ExtractPolicy::All);
+        assert!(cli.exclude_vcs);
+
+        #[cfg(feature = "extract")]
+        {
+            // the archive is extracted, so its code is the code of its files
+            use crate::process::{self, Processor, ProcessOptions};
+            use file_verification_code::{FVCHasher, FVC2Hasher};
+
+            let mut hasher = FVC2Hasher::new();
+            process::new(cli.extract, ProcessOptions::default()).calculate_fvc(&mut hasher, &cli.files[..]).expect("processing archive");
+            assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+        }
+
+        let cli = parse_with(&config, &["fvc", "--extract", "none", "test_data/flat_archive.tar.gz"]);
+        assert_eq!(cli.extract, ExtractPolicy::None);
+    }
+
+    #[test]
+    fn unknown_option_is_rejected() {
+        assert!(Config::parse("extrakt = \"all\"\n").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Progress and results as a stream of newline-delimited JSON events, for job runners and other orchestrators
+//! Every line is one JSON object with an `event` field naming the kind of event
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+use log::*;
+use file_verification_code::progress::ProgressReporter;
+
+/// Event is a single line of the event stream
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// a file was hashed and will be part of the code
+    FileHashed { path: &'a Path, sha256: String, size: u64 },
+    /// an archive was extracted and its contents processed
+    ArchiveExtracted { path: &'a Path, sha256: String },
+    /// processing finished with the given code
+    Done { fvc: &'a str },
+}
+
+/// EventLog writes events to a file or stdout, it can be cloned to share the same destination
+#[derive(Clone)]
+pub struct EventLog {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl std::fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventLog")
+    }
+}
+
+impl EventLog {
+    /// create writes events to the given file, or to stdout if the path is `-`
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        if path == Path::new("-") {
+            Ok(EventLog::from_writer(std::io::stdout()))
+        } else {
+            Ok(EventLog::from_writer(std::io::BufWriter::new(std::fs::File::create(path)?)))
+        }
+    }
+
+    /// from_writer writes events to the given writer
+    pub fn from_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        EventLog { writer: Arc::new(Mutex::new(Box::new(writer))) }
+    }
+
+    /// emit writes the event as one line
+    /// Failing to write an event is logged rather than interrupting processing
+    pub fn emit(&self, event: &Event) {
+        self.write(event);
+    }
+
+    /// write writes any serializable value as one line, like emit
+    pub fn write<T: Serialize + std::fmt::Debug>(&self, event: &T) {
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        let written = serde_json::to_writer(&mut *writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"))
+            .and_then(|()| writer.flush());
+        if let Err(err) = written {
+            error!("error writing event {:?}: {}", event, err);
+        }
+    }
+}
+
+/// The event stream reports progress as events
+impl ProgressReporter for EventLog {
+    fn on_file(&self, path: &Path, sha256: &[u8; 32], size: u64) {
+        self.emit(&Event::FileHashed { path, sha256: hex::encode(sha256), size });
+    }
+
+    fn on_archive(&self, path: &Path, sha256: &[u8; 32]) {
+        self.emit(&Event::ArchiveExtracted { path, sha256: hex::encode(sha256) });
+    }
+
+    fn on_done(&self, fvc: &str) {
+        self.emit(&Event::Done { fvc });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{self, Processor, ProcessOptions};
+    use file_verification_code::{FVCHasher, FVC2Hasher};
+
+    #[test]
+    fn done_event_carries_fvc() {
+        let tmp = tempdir::TempDir::new("fvc_events").expect("creating temporary directory");
+        let log_path = tmp.path().join("events.ndjson");
+        let events = EventLog::create(&log_path).expect("creating event log");
+
+        let mut hasher = FVC2Hasher::new();
+        let options = ProcessOptions { progress: Some(std::sync::Arc::new(events.clone())), ..Default::default() };
+        process::new(process::default_policy(), options).calculate_fvc(&mut hasher, &[std::path::PathBuf::from("test_data/flat_files")]).expect("processing files");
+        let fvc = hasher.hex();
+        events.on_done(&fvc);
+        drop(events);
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&log_path).expect("reading event log").lines()
+            .map(|line| serde_json::from_str(line).expect("parsing event"))
+            .collect();
+        assert_eq!(lines.iter().filter(|event| event["event"] == "file_hashed").count(), 3);
+        let done = lines.last().expect("reading last event");
+        assert_eq!(done["event"], "done");
+        assert_eq!(done["fvc"], fvc.as_str());
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Verify lists of files against the sha256s they are expected to have, while adding them to the file verification code
+//! Each non-empty line of a list is `path expected_sha256`, lines starting with `#` are ignored
+
+use file_verification_code::{FVC2Hasher, FVCSha256Hasher};
+use file_verification_code::digest::sha256_of;
+
+use std::io::BufRead;
+use std::path::PathBuf;
+use log::*;
+
+/// Expected is a file and the sha256 it is expected to have
+#[derive(Debug, PartialEq)]
+pub struct Expected {
+    pub path: PathBuf,
+    pub sha256: [u8; 32]
+}
+
+/// Mismatch is a listed file whose current content does not have its expected sha256
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    pub path: PathBuf,
+    pub expected: [u8; 32],
+    pub actual: [u8; 32]
+}
+
+/// parse_expected reads `path expected_sha256` lines
+/// The hash is taken from after the last whitespace, so paths may contain spaces
+pub fn parse_expected<R: BufRead>(reader: R) -> std::io::Result<Vec<Expected>> {
+    let mut entries = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, reason));
+        let (path, sha256_hex) = match line.rsplit_once(char::is_whitespace) {
+            Some((path, sha256_hex)) => (path.trim_end(), sha256_hex),
+            None => return Err(invalid("expected `path sha256`"))
+        };
+        let mut sha256 = [0u8; 32];
+        if hex::decode_to_slice(sha256_hex, &mut sha256).is_err() {
+            return Err(invalid("expected sha256 is not 64 hex characters"));
+        }
+
+        entries.push(Expected { path: PathBuf::from(path), sha256 });
+    }
+
+    Ok(entries)
+}
+
+/// verify_expected hashes every listed file, adds it to the hasher, and returns the files that did not match their expected sha256
+pub fn verify_expected(hasher: &mut FVC2Hasher, entries: &[Expected]) -> std::io::Result<Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+    for entry in entries {
+        info!("Verifying file \"{}\"", entry.path.display());
+        let (actual, _size) = sha256_of(std::fs::File::open(&entry.path)?)?;
+        hasher.read_sha256(actual);
+
+        if actual != entry.sha256 {
+            mismatches.push(Mismatch { path: entry.path.clone(), expected: entry.sha256, actual });
+        }
+    }
+
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use file_verification_code::FVCHasher;
+    use hex_literal::hex;
+
+    #[test]
+    fn mismatch_is_reported_and_fvc_computed() {
+        let list = "\
+# flat files
+test_data/flat_files/foo.txt b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c
+test_data/flat_files/bar.txt 0000000000000000000000000000000000000000000000000000000000000000
+";
+        let entries = parse_expected(list.as_bytes()).expect("parsing list");
+        assert_eq!(entries.len(), 2);
+
+        let mut hasher = FVC2Hasher::new();
+        let mismatches = verify_expected(&mut hasher, &entries).expect("verifying list");
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, PathBuf::from("test_data/flat_files/bar.txt"));
+        assert_eq!(mismatches[0].actual, sha256_of(std::fs::File::open("test_data/flat_files/bar.txt").unwrap()).unwrap().0);
+
+        // mismatched files are still part of the code
+        let mut direct = FVC2Hasher::new();
+        direct.read_sha256(hex!("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"));
+        direct.read_sha256(sha256_of(std::fs::File::open("test_data/flat_files/bar.txt").unwrap()).unwrap().0);
+        assert_eq!(hasher.hex(), direct.hex());
+    }
+
+    #[test]
+    fn invalid_line_is_rejected() {
+        let err = parse_expected("test_data/flat_files/foo.txt nothex".as_bytes()).expect_err("parsing invalid list");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
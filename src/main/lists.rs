@@ -0,0 +1,76 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Read the files to process from lists, for input sets too large for the command line
+//! Each non-empty line of a list is one path, lines starting with `#` are ignored
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use log::*;
+
+/// parse_list reads one path per line
+pub fn parse_list<R: BufRead>(reader: R) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        paths.push(PathBuf::from(line));
+    }
+
+    Ok(paths)
+}
+
+/// read_list_dir reads every `*.txt` list in dir, in name order, and returns all their paths
+/// Paths are used as written, so relative paths are relative to the current directory rather than to the list
+pub fn read_list_dir(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut lists = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension() == Some(std::ffi::OsStr::new("txt")) {
+            lists.push(path);
+        }
+    }
+    lists.sort();
+
+    let mut paths = Vec::new();
+    for list in lists {
+        debug!("reading file list {}", list.display());
+        let file = std::fs::File::open(&list)?;
+        paths.extend(parse_list(std::io::BufReader::new(file))?);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{self, Processor, ProcessOptions};
+    use file_verification_code::{FVCHasher, FVC2Hasher};
+
+    #[test]
+    fn lists_in_dir_are_aggregated() {
+        let tmp = tempdir::TempDir::new("fvc_lists").expect("creating temporary directory");
+        std::fs::write(tmp.path().join("first.txt"), "test_data/flat_files/foo.txt\n\n# the rest is in second.txt\n").unwrap();
+        std::fs::write(tmp.path().join("second.txt"), "test_data/flat_files/bar.txt\ntest_data/flat_files/zap.txt\n").unwrap();
+        std::fs::write(tmp.path().join("ignored.csv"), "test_data/flat_archive.tar\n").unwrap();
+
+        let paths = read_list_dir(tmp.path()).expect("reading list directory");
+        assert_eq!(paths.len(), 3);
+
+        let mut hasher = FVC2Hasher::new();
+        process::new(process::default_policy(), ProcessOptions::default()).calculate_fvc(&mut hasher, &paths[..]).expect("processing listed files");
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+}
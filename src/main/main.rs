@@ -11,15 +11,34 @@
 //! `fvc` is a utility that will collect all the files it is given and calculate a file verification code of all of them
 
 mod process;
-use process::{Processor, ExtractPolicy};
+use process::{Processor, ExtractPolicy, ProcessOptions};
+mod expected;
+mod audit;
+mod config;
+mod events;
+mod metadata;
+mod lists;
+#[cfg(feature = "extract")]
+mod compare;
+#[cfg(feature = "extract")]
+mod sbom;
+#[cfg(all(feature = "extract", feature = "tar"))]
+mod bundle;
+#[cfg(unix)]
+mod socket;
+#[cfg(feature = "git")]
+mod pathspec;
+mod selftest;
 use file_verification_code::FVCHasher;
 use file_verification_code::FVC2Hasher;
+use file_verification_code::progress::ProgressReporter;
 
 use std::io::Write;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
 use std::path::PathBuf;
-use log::{debug};
+use log::{debug, info, warn};
 use colored::Colorize;
+use serde::Serialize;
 
 #[derive(Parser, Debug)]
 #[command(version)] // causes version to be read from Cargo.toml
@@ -31,20 +50,179 @@ struct CLI {
     #[arg(long, action=clap::ArgAction::Version)] // manually define --version flag since we are using v for verbosity
     #[arg(short='e', long="examples", action=clap::ArgAction::SetTrue)]
     show_examples: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="list-extensions", help="List the file extensions considered archives and exit")]
+    list_extensions: bool,
+    #[arg(long, help="Check that this build gives the known codes of the bundled test fixtures and exit, nonzero on any mismatch")]
+    selftest: bool,
     // since neither -h nor --help are in use, help arg is auto-generated
 
     #[arg(short='v', long="verbose", help="Include more v's for higher verbosity", action=clap::ArgAction::Count)]
     verbose: u8,
+    #[arg(long, value_name="FILE", help="Read default options from the given TOML file instead of .fvcrc or fvc.toml in the current directory")]
+    config: Option<PathBuf>,
     #[arg(short='b', long="binary", help="Output FVC in binary form instead of hex-encoded string")]
     binary_mode: bool,
     #[arg(short, long, help="Output to given file")]
     output: Option<PathBuf>,
+    #[cfg(unix)]
+    #[cfg_attr(feature = "extract", arg(conflicts_with = "sbom"))]
+    #[arg(long="output-fd", value_name="N", conflicts_with_all=["output", "format", "all_versions"], help="Write the binary FVC to the given open file descriptor, such as a pipe from a parent process")]
+    output_fd: Option<i32>,
+    #[arg(long, conflicts_with="binary_mode", help="Print the hex code, of every input too with --sidecar or --format json, in uppercase")]
+    uppercase: bool,
+    #[arg(long="banner-to", value_enum, default_value_t=BannerDestination::Stderr, help="Where to print the \"FVC: \" label when printing the hex code")]
+    banner_to: BannerDestination,
     #[arg(long, value_enum, default_value_t=process::default_policy(), help="How to decide what files to try extracting")]
     extract: ExtractPolicy, 
+    #[arg(long="exclude-vcs", help="Skip version control metadata directories (.git, .svn, .hg, .bzr)")]
+    exclude_vcs: bool,
+    #[arg(long="ignore-generated", help="Skip commonly generated files, like *.pyc and *.o, for a normalized source FVC")]
+    ignore_generated: bool,
+    #[arg(long="ignore-extension", value_name="EXT", help="Skip files with the given extension, in addition to --ignore-generated; may be given more than once")]
+    ignore_extension: Vec<String>,
+    #[arg(long="exclude-hash", value_name="HEX", value_parser=parse_sha256, help="Skip files with the given sha256, such as license boilerplate; may be given more than once")]
+    exclude_hash: Vec<[u8; 32]>,
+    #[arg(long, value_name="STR", help="Scope the code to a namespace, giving a namespaced variant of FVC2 that differs for every namespace")]
+    namespace: Option<String>,
+    #[arg(long="build-id", value_name="STR", help="Fold a build or version identifier into the code, giving a variant of FVC2 that differs for every build id")]
+    build_id: Option<String>,
+    #[arg(long, help="Also print the number of members of the code, and how many of them are unique, to stderr")]
+    stats: bool,
+    #[arg(long, value_name="FILE", help="Verify the files listed as `path expected_sha256` lines and include them in the FVC")]
+    expected: Option<PathBuf>,
+    #[arg(long="normalize-whitespace", help="Hash text files with trailing spaces and tabs stripped from every line and trailing newlines dropped, giving a distinct code")]
+    normalize_whitespace: bool,
+    #[arg(long="max-dir-entries", value_name="N", help="Fail on any directory with more than N entries, instead of walking it")]
+    max_dir_entries: Option<usize>,
+    #[arg(long, value_name="FILE", help="Stream progress and the result as newline-delimited JSON events to the given file, or stdout if `-`")]
+    events: Option<PathBuf>,
+    #[arg(long="audit-log", value_name="FILE", help="Record what was done with every file, and why, as newline-delimited JSON to the given file, or stdout if `-`")]
+    audit_log: Option<PathBuf>,
+    #[cfg_attr(feature = "extract", arg(conflicts_with = "tree"))]
+    #[arg(long="metadata-only", conflicts_with_all=["expected", "sidecar"], help="Hash every file's relative path, size and modification time instead of its content, giving a cheap change-detection code that is not an FVC2 code")]
+    metadata_only: bool,
+    #[arg(long="strip-components", value_name="N", default_value_t=0, requires="metadata_only", help="Remove the first N components of every path before hashing it with --metadata-only, skipping files with no more components than that, like tar")]
+    strip_components: usize,
+    #[cfg_attr(feature = "extract", arg(conflicts_with = "tree"))]
+    #[arg(long, help="Also write each input's own FVC next to it, as <input>.fvc")]
+    sidecar: bool,
+    #[cfg_attr(feature = "extract", arg(conflicts_with = "tree"))]
+    #[arg(long, value_enum, default_value_t=OutputFormat::Hex, conflicts_with_all=["binary_mode", "metadata_only", "expected"], help="Output format of the code, json gives the code of every input on its own")]
+    format: OutputFormat,
+    #[arg(long="verify-stable", help="Process the files a second time and fail if the two codes differ, as they do when the files change during processing")]
+    verify_stable: bool,
+    #[arg(long="all-versions", conflicts_with_all=["binary_mode", "format"], help="Print the code of every supported FVC version, one `FVC<version>: <code>` line each, calculated from the same files")]
+    all_versions: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long, value_name="FILE", help="Write the tree of processed files and archives to the given file as JSON")]
+    tree: Option<PathBuf>,
+    #[cfg(all(feature = "extract", feature = "tar"))]
+    #[arg(long, value_name="FILE", conflicts_with_all=["metadata_only", "sidecar", "format", "expected"], help="Bundle every file that contributed to the code, archives as they are, into a reproducible tar at the given file")]
+    bundle: Option<PathBuf>,
+    #[cfg(feature = "extract")]
+    #[arg(long, value_enum, conflicts_with_all=["binary_mode", "metadata_only", "sidecar", "format", "expected"], help="Print a software bill of materials listing every contributing file and its sha256, with the FVC as a property, instead of the FVC")]
+    sbom: Option<SbomFormat>,
+    #[cfg(feature = "extract")]
+    #[arg(long, value_name="N", help="Also print the N largest files that contributed to the code, with their sha256s, to stderr")]
+    top: Option<usize>,
+    #[cfg(feature = "extract")]
+    #[arg(long="archive-duplicates", help="Also report, to stderr, the members of every archive that have the same content as another member of it")]
+    archive_duplicates: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="manifest-hash", requires="tree", help="Also print the sha256 of the tree written with --tree to stderr, to cross-check it against the FVC")]
+    manifest_hash: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long, requires="tree", help="Record the FVC of every directory and archive in the tree, Merkle-style")]
+    merkle: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="entry-order", requires="tree", help="Record the order every extracted archive stores its entries in, in the tree")]
+    entry_order: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="compare-trees", num_args=2, value_names=["OLD", "NEW"], help="Compare two trees written with --tree, printing changed files and whether their FVCs match, instead of processing files")]
+    compare_trees: Option<Vec<PathBuf>>,
+    #[cfg(feature = "extract")]
+    #[arg(long="fail-on-cycle", conflicts_with="no_cycle_detection", help="Fail on an archive that contains itself, such as a quine, instead of warning and skipping it")]
+    fail_on_cycle: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="no-cycle-detection", help="Skip detecting archives that extract to themselves, for speed on trusted input; quines are only stopped by the archive depth limit")]
+    no_cycle_detection: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="uncompressed-size", requires="tree", help="Record every extracted archive's uncompressed size in the tree, with a metadata hash of its contents and that size")]
+    uncompressed_size: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="flatten-single", requires="tree", help="Record archives holding a single file, like foo.txt.gz, as just that file in the tree")]
+    flatten_single: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="strict-extract", help="Fail on truncated or corrupt gzip and zstd streams instead of hashing them as files")]
+    strict_extract: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="max-archive-size", value_name="BYTES", help="Hash archives whose extracted files add up to more than the given size as files, each archive being limited on its own")]
+    max_archive_size: Option<u64>,
+    #[cfg(feature = "extract")]
+    #[arg(long="package-payload", help="Hash only the files .deb packages install, from their data archive, leaving out their control archive")]
+    package_payload: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long, value_enum, default_value_t=process::SymlinkMode::Skip, help="What symlinks in walked directories and extracted archives contribute to the FVC")]
+    symlinks: process::SymlinkMode,
+    #[cfg(feature = "extract")]
+    #[arg(long="hash-archive-file", help="Extract archives into the tree as usual, but fold each outermost archive's own sha256 into the FVC instead of its contents")]
+    hash_archive_file: bool,
+    #[cfg(feature = "extract")]
+    #[arg(long="exclude-archive-junk", help="Skip __MACOSX, .DS_Store and Thumbs.db in extracted archives, which changes the FVC")]
+    exclude_archive_junk: bool,
+    #[cfg(feature = "zstd")]
+    #[arg(long="zstd-dict", value_name="FILE", help="Decompress zstd-compressed archives with the given dictionary")]
+    zstd_dict: Option<PathBuf>,
+    #[cfg(feature = "parallel")]
+    #[arg(long, help="Hash the files of every directory on all cores before processing them, which gives the same FVC")]
+    parallel: bool,
+    #[arg(long="files-from-dir", value_name="DIR", help="Also process the paths listed one per line in every *.txt file in the given directory")]
+    files_from_dir: Option<PathBuf>,
+    #[cfg(feature = "git")]
+    #[arg(long, value_name="GLOB", help="Only process the files tracked in the git repository of every input that are under it and match the given git pathspec, which may be given more than once")]
+    pathspec: Vec<String>,
+    #[cfg(unix)]
+    #[arg(long="unix-socket", value_name="PATH", help="Also process the bytes read from the given Unix domain socket, as a file without an extension that is only extracted with --extract all")]
+    unix_socket: Option<PathBuf>,
+    #[arg(long, help="Resolve every input to its canonical path and process each only once, so a file given both directly and through a symlink contributes once")]
+    canonicalize: bool,
     #[arg(help="Files or directory of files to calculate file verification code of")]
     files: Vec<PathBuf>,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum BannerDestination {
+    /// Print the label on stdout, on the same line as the code
+    Stdout,
+    /// Print the label on stderr, so stdout only holds the code
+    Stderr,
+    /// Don't print the label
+    None
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Print the code of all inputs together as hex
+    Hex,
+    /// Print a JSON array of the code and file count of every input on its own
+    Json
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum SbomFormat {
+    /// A minimal CycloneDX JSON document
+    Cyclonedx
+}
+
+/// InputCode is the code of one of the given inputs on its own
+#[derive(Debug, Serialize)]
+struct InputCode {
+    input: PathBuf,
+    fvc: String,
+    file_count: usize,
+}
+
 // format an examples string stylized similarly to clap's help
 fn get_examples() -> String {
     format!(r#"{header}
@@ -75,7 +253,11 @@ Redirect a binary File Verification Code to a file
 }
 
 fn main() {
-    let cli = CLI::parse(); // parse command line
+    let matches = CLI::command().get_matches(); // parse command line
+    let mut cli = match CLI::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(err) => err.exit()
+    };
 
     // initialize logger
     stderrlog::new()
@@ -100,30 +282,733 @@ fn main() {
         std::process::exit(0);
     }
 
+    #[cfg(feature = "extract")]
+    if cli.list_extensions {
+        // print archive extensions and exit
+        for extension in file_verification_code::extract::archive_extensions() {
+            println!("{}", extension);
+        }
+        std::process::exit(0);
+    }
+
+    if cli.selftest {
+        // check the known vectors and exit
+        let passed = selftest::run().expect("running selftest");
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    // fill in defaults from the config file, flags given on the command line take precedence
+    let config = config::Config::load(cli.config.as_deref()).expect("reading config file");
+    config.apply(&mut cli, &matches);
+
+    if let Some(dir) = &cli.files_from_dir {
+        let listed = lists::read_list_dir(dir).expect("reading file lists");
+        cli.files.extend(listed);
+    }
+
+    // the pathspec replaces the inputs with the tracked files it selects under them, the current directory if none are given
+    #[cfg(feature = "git")]
+    if !cli.pathspec.is_empty() {
+        if cli.files.is_empty() {
+            cli.files.push(PathBuf::from("."));
+        }
+        cli.files = pathspec::tracked_files(&cli.files, &cli.pathspec).expect("matching pathspec against tracked files");
+    }
+
+    // the received stream is kept in a temporary directory until processing is done
+    #[cfg(unix)]
+    let _received = match &cli.unix_socket {
+        Some(socket) => {
+            let tmp = tempdir::TempDir::new("fvc_socket").expect("creating temporary directory");
+            let received = socket::receive(socket, tmp.path()).expect("receiving from unix socket");
+            cli.files.push(received);
+            Some(tmp)
+        },
+        None => None
+    };
+
+    if cli.canonicalize {
+        cli.files = canonical_inputs(&cli.files).expect("canonicalizing given files");
+    }
+
     debug!("CLI: {:?}", cli);
 
+    // comparing saved trees does not touch any files, so it replaces processing altogether
+    #[cfg(feature = "extract")]
+    if let Some(trees) = &cli.compare_trees {
+        let old = compare::load_tree(&trees[0]).expect("reading old tree");
+        let new = compare::load_tree(&trees[1]).expect("reading new tree");
+        let diff = compare::compare_trees(&old, &new);
+        println!("{}", diff);
+        std::process::exit(if diff.fvcs_match() { 0 } else { 1 });
+    }
+
     // traverse given files and calculate file verification code of all of them
-    let mut hasher = FVC2Hasher::new();
-    let processor = process::new(cli.extract);
-    processor.calculate_fvc(&mut hasher, &cli.files[..]).expect("processing given files");
+    let mut hasher = new_hasher(&cli);
+    // members added for --namespace and --build-id are not files
+    let salt_members = hasher.len();
+    let events = cli.events.as_deref().map(|path| events::EventLog::create(path).expect("creating events file"));
+    let options = ProcessOptions {
+        exclude_vcs: cli.exclude_vcs,
+        ignored_extensions: ignored_extensions(&cli),
+        excluded_hashes: cli.exclude_hash.clone(),
+        normalize_whitespace: cli.normalize_whitespace,
+        max_dir_entries: cli.max_dir_entries,
+        strip_components: cli.strip_components,
+        progress: events.clone().map(|events| std::sync::Arc::new(events) as std::sync::Arc<dyn ProgressReporter>),
+        audit: cli.audit_log.as_deref().map(|path| audit::AuditLog::create(path).expect("creating audit log")),
+        cancel: None,
+        #[cfg(feature = "extract")]
+        entry_order: cli.entry_order,
+        #[cfg(feature = "extract")]
+        no_cycle_detection: cli.no_cycle_detection,
+        #[cfg(feature = "extract")]
+        fail_on_cycle: cli.fail_on_cycle,
+        #[cfg(feature = "extract")]
+        cycles_detected: Default::default(),
+        #[cfg(feature = "extract")]
+        uncompressed_size: cli.uncompressed_size,
+        #[cfg(feature = "extract")]
+        flatten_single: cli.flatten_single,
+        #[cfg(feature = "extract")]
+        strict_extract: cli.strict_extract,
+        #[cfg(feature = "extract")]
+        exclude_archive_junk: cli.exclude_archive_junk,
+        #[cfg(feature = "extract")]
+        max_archive_size: cli.max_archive_size,
+        #[cfg(feature = "extract")]
+        package_payload: cli.package_payload,
+        #[cfg(feature = "extract")]
+        hash_archive_file: cli.hash_archive_file,
+        #[cfg(feature = "extract")]
+        symlinks: cli.symlinks,
+        #[cfg(feature = "zstd")]
+        zstd_dictionary: cli.zstd_dict.as_deref().map(|path| file_verification_code::extract::ZstdDictionary::read(path).expect("reading zstd dictionary")),
+        #[cfg(feature = "parallel")]
+        parallel: cli.parallel,
+    };
+    let processor = process::new(cli.extract, options.clone());
+    let mut per_input = Vec::new();
+    #[cfg_attr(not(feature = "extract"), allow(unused_mut))]
+    let mut sbom: Option<String> = None;
+    #[cfg_attr(not(feature = "extract"), allow(unused_mut))]
+    let mut manifest_sha256: Option<[u8; 32]> = None;
+    if cli.metadata_only {
+        metadata::hash_metadata(&mut hasher, &cli.files[..], &options).expect("reading metadata of given files");
+    } else if cli.sidecar || cli.format == OutputFormat::Json {
+        per_input = code_per_input(&processor, &cli, &mut hasher).expect("processing given files");
+        if cli.sidecar {
+            write_sidecars(&per_input).expect("writing sidecars");
+        }
+    } else {
+        #[cfg(all(feature = "extract", feature = "tar"))]
+        let bundle = cli.bundle.as_deref();
+        #[cfg(all(feature = "extract", not(feature = "tar")))]
+        let bundle: Option<&std::path::Path> = None;
+        #[cfg(feature = "extract")]
+        match (&cli.tree, bundle, cli.top, cli.sbom, cli.archive_duplicates) {
+            (None, None, None, None, false) => processor.calculate_fvc(&mut hasher, &cli.files[..]).expect("processing given files"),
+            (tree, _, top, sbom_format, archive_duplicates) => {
+                let mut collections = processor.collect(&cli.files[..]).expect("processing given files");
+                if let Some(tree) = tree {
+                    if cli.merkle {
+                        for collection in collections.iter_mut() {
+                            collection.merkle();
+                        }
+                    }
+                    let sha256 = write_tree(tree, &collections).expect("writing tree to file");
+                    if cli.manifest_hash {
+                        manifest_sha256 = Some(sha256);
+                    }
+                }
+                #[cfg(feature = "tar")]
+                if let Some(bundle) = bundle {
+                    bundle::write_bundle(bundle, &cli.files[..], &collections).expect("writing bundle");
+                }
+                if let Some(top) = top {
+                    for (path, file) in largest_files(&collections, top) {
+                        eprintln!("{}\t{}\t{}", file.size, hex::encode(file.sha256), path.display());
+                    }
+                }
+                if archive_duplicates {
+                    eprint!("{}", duplicate_report(&collections));
+                }
+                processor.hash_collections(&mut hasher, &collections);
+                sbom = sbom_format.map(|SbomFormat::Cyclonedx| {
+                    serde_json::to_string_pretty(&sbom::cyclonedx(&collections, &hex_of(&cli, &mut hasher))).expect("serializing sbom")
+                });
+            }
+        };
+        #[cfg(not(feature = "extract"))]
+        processor.calculate_fvc(&mut hasher, &cli.files[..]).expect("processing given files");
+    }
+
+    info!("hashed {} files", hasher.len() - salt_members);
+
+    if cli.verify_stable {
+        verify_stable(&cli, &options, &hasher.hex()).expect("verifying the code is stable");
+    }
+
+    let mut mismatched = false;
+    if let Some(list) = &cli.expected {
+        let file = std::fs::File::open(list).expect("opening expected list");
+        let entries = expected::parse_expected(std::io::BufReader::new(file)).expect("parsing expected list");
+        for mismatch in expected::verify_expected(&mut hasher, &entries[..]).expect("verifying expected list") {
+            warn!("{} does not match its expected sha256: expected {}, found {}", mismatch.path.display(), hex::encode(mismatch.expected), hex::encode(mismatch.actual));
+            mismatched = true;
+        }
+    }
 
-    match cli.output {
-        Some(path) => {
+    if let Some(events) = &events {
+        events.on_done(&hasher.hex());
+    }
+
+    match (&cli.output, cli.format) {
+        (Some(path), _) if sbom.is_some() => {
+            std::fs::write(path, sbom.unwrap()).expect("writing sbom to file");
+        },
+        (None, _) if sbom.is_some() => {
+            println!("{}", sbom.unwrap());
+        },
+        #[cfg(unix)]
+        (None, OutputFormat::Hex) if cli.output_fd.is_some() => {
+            // Write to a file descriptor passed by a parent process
+            write_to_fd(cli.output_fd.unwrap(), &hasher.sum()).expect("writing binary fvc to file descriptor");
+        },
+        (Some(path), OutputFormat::Json) => {
+            std::fs::write(path, serde_json::to_string_pretty(&per_input).expect("serializing codes")).expect("writing json to file");
+        },
+        (None, OutputFormat::Json) => {
+            println!("{}", serde_json::to_string_pretty(&per_input).expect("serializing codes"));
+        },
+        (Some(path), OutputFormat::Hex) if cli.all_versions => {
+            std::fs::write(path, all_versions(&cli, &mut hasher, file_verification_code::SUPPORTED_VERSIONS)).expect("writing fvcs to file");
+        },
+        (None, OutputFormat::Hex) if cli.all_versions => {
+            print!("{}", all_versions(&cli, &mut hasher, file_verification_code::SUPPORTED_VERSIONS));
+        },
+        (Some(path), OutputFormat::Hex) => {
             // Write to file
             if cli.binary_mode {
-                std::fs::write(&path, hasher.sum()).expect("writing binary fvc to file");
+                std::fs::write(path, hasher.sum()).expect("writing binary fvc to file");
             } else {
-                std::fs::write(&path, hasher.hex()).expect("writing hex fvc to file");
+                std::fs::write(path, hex_of(&cli, &mut hasher)).expect("writing hex fvc to file");
             }
         },
-        None => {
+        (None, OutputFormat::Hex) => {
             // Print to stdout
             if cli.binary_mode {
                 std::io::stdout().write_all(&hasher.sum()[..]).expect("writing binary to stdout");
             } else {
-                eprint!("FVC: ");
-                println!("{}", hasher.hex());        
+                print_hex(&label(&cli), &hex_of(&cli, &mut hasher), cli.banner_to, &mut std::io::stdout(), &mut std::io::stderr()).expect("writing hex to stdout");
             }
         }
     }
-}
\ No newline at end of file
+
+    if let Some(sha256) = manifest_sha256 {
+        eprintln!("Manifest sha256: {}", hex::encode(sha256));
+    }
+
+    if cli.stats {
+        #[cfg(feature = "extract")]
+        let cycles = options.cycles_detected.load(std::sync::atomic::Ordering::Relaxed);
+        #[cfg(not(feature = "extract"))]
+        let cycles = 0;
+        eprintln!("{}", stats(&mut hasher, cycles));
+    }
+
+    if mismatched {
+        std::process::exit(1);
+    }
+}
+
+// hex_of returns the hex code of hasher, in uppercase if asked for
+fn hex_of(cli: &CLI, hasher: &mut FVC2Hasher) -> String {
+    match cli.uppercase {
+        true => hasher.hex().to_uppercase(),
+        false => hasher.hex()
+    }
+}
+
+// verify_stable processes the given files a second time, failing if their code is not first
+// The second pass reports neither progress nor audit records, which the first already did
+fn verify_stable(cli: &CLI, options: &ProcessOptions, first: &str) -> std::io::Result<()> {
+    let options = ProcessOptions {
+        progress: None,
+        audit: None,
+        #[cfg(feature = "extract")]
+        cycles_detected: Default::default(),
+        ..options.clone()
+    };
+    let mut hasher = new_hasher(cli);
+    if cli.metadata_only {
+        metadata::hash_metadata(&mut hasher, &cli.files[..], &options)?;
+    } else {
+        process::new(cli.extract, options).calculate_fvc(&mut hasher, &cli.files[..])?;
+    }
+
+    let second = hasher.hex();
+    if second != first {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("files changed during processing, the first pass gave {} and the second {}", first, second)));
+    }
+
+    Ok(())
+}
+
+// parse_sha256 parses a sha256 given as 64 hex characters
+fn parse_sha256(sha256_hex: &str) -> Result<[u8; 32], String> {
+    let mut sha256 = [0u8; 32];
+    match hex::decode_to_slice(sha256_hex, &mut sha256) {
+        Ok(()) => Ok(sha256),
+        Err(_) => Err("a sha256 is 64 hex characters".to_string())
+    }
+}
+
+// all_versions returns a `FVC<version>: <code>` line for every given version, each folded from the same member sha256s
+fn all_versions(cli: &CLI, hasher: &mut FVC2Hasher, versions: &[(u8, file_verification_code::VersionFold)]) -> String {
+    let mut lines = String::new();
+    for (version, fold) in versions {
+        let code = hex::encode(fold(hasher));
+        let code = match cli.uppercase {
+            true => code.to_uppercase(),
+            false => code
+        };
+        lines.push_str(&format!("FVC{}: {}\n", version, code));
+    }
+
+    lines
+}
+
+// ignored_extensions lists the extensions of files to skip, the generated preset if asked for and every --ignore-extension
+fn ignored_extensions(cli: &CLI) -> Vec<String> {
+    let mut extensions: Vec<String> = cli.ignore_extension.iter().map(|extension| extension.trim_start_matches('.').to_string()).collect();
+    if cli.ignore_generated {
+        extensions.extend(process::GENERATED_EXTENSIONS.iter().map(|extension| extension.to_string()));
+    }
+
+    extensions
+}
+
+// canonical_inputs resolves every input to its canonical path, keeping only the first of those resolving to the same file
+// Walked directories need no such care, since their links are recorded rather than followed
+fn canonical_inputs(files: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut canonical: Vec<PathBuf> = Vec::new();
+    let mut identities = Vec::new();
+    for path in files {
+        let resolved = std::fs::canonicalize(path)?;
+        let identity = file_identity(&resolved)?;
+        if canonical.contains(&resolved) || (identity.is_some() && identities.contains(&identity)) {
+            debug!("{} was already given as {}", path.display(), resolved.display());
+            continue;
+        }
+        canonical.push(resolved);
+        identities.push(identity);
+    }
+
+    Ok(canonical)
+}
+
+// file_identity returns the device and inode of path, telling the same file apart even under paths canonicalize leaves different,
+// such as case variations on a case-insensitive filesystem like macOS's, where canonical paths keep the case they were given in
+#[cfg(unix)]
+fn file_identity(path: &std::path::Path) -> std::io::Result<Option<(u64, u64)>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path)?;
+    Ok(Some((metadata.dev(), metadata.ino())))
+}
+
+// file_identity has nothing to add elsewhere: on Windows canonicalize already returns the case a file is stored with
+#[cfg(not(unix))]
+fn file_identity(_path: &std::path::Path) -> std::io::Result<Option<(u64, u64)>> {
+    Ok(None)
+}
+
+// new_hasher creates the hasher for the code asked for on the command line
+fn new_hasher(cli: &CLI) -> FVC2Hasher {
+    let mut hasher = FVC2Hasher::new();
+    if let Some(namespace) = &cli.namespace {
+        hasher.read_namespace(namespace);
+    }
+    if let Some(build_id) = &cli.build_id {
+        hasher.read_build_id(build_id);
+    }
+
+    hasher
+}
+
+// code_per_input processes every input on its own, returning the code of each, and adds its files to hasher
+fn code_per_input<P: Processor>(processor: &P, cli: &CLI, hasher: &mut FVC2Hasher) -> std::io::Result<Vec<InputCode>> {
+    let mut codes = Vec::new();
+    for input in cli.files.iter() {
+        let mut files = FVC2Hasher::new();
+        processor.calculate_fvc(&mut files, std::slice::from_ref(input))?;
+
+        let mut code = new_hasher(cli);
+        code.extend_from(&files);
+        codes.push(InputCode { input: input.clone(), fvc: hex_of(cli, &mut code), file_count: files.len() });
+
+        hasher.merge(files);
+    }
+
+    Ok(codes)
+}
+
+// write_tree writes the given trees to path as JSON, and returns the sha256 of what was written
+#[cfg(feature = "extract")]
+fn write_tree(path: &std::path::Path, collections: &[file_verification_code::archive_tree::Collection]) -> std::io::Result<[u8; 32]> {
+    let json = serde_json::to_string_pretty(collections)?;
+    std::fs::write(path, &json)?;
+
+    Ok(file_verification_code::digest::sha256_of(json.as_bytes())?.0)
+}
+
+// write_sidecars writes the code of every input to <input>.fvc next to it
+fn write_sidecars(codes: &[InputCode]) -> std::io::Result<()> {
+    for code in codes {
+        // collecting the components drops any trailing separator, so a directory's sidecar is next to it rather than in it
+        let mut sidecar = code.input.components().collect::<PathBuf>().into_os_string();
+        sidecar.push(".fvc");
+        std::fs::write(&sidecar, &code.fvc)?;
+    }
+
+    Ok(())
+}
+
+// write_to_fd writes bytes to an open file descriptor, and closes it so the reading end sees the end of the code
+// stdin, stdout and stderr are refused, since closing them would take them from the rest of fvc
+#[cfg(unix)]
+fn write_to_fd(fd: i32, bytes: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::io::FromRawFd;
+
+    if fd <= 2 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("file descriptor {} is stdin, stdout or stderr, print the code without --output-fd instead", fd)));
+    }
+    // only an open descriptor may be owned by a File
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // the descriptor was handed to us for the code, so it is ours to write to and close
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(bytes)
+}
+
+// stats describes how many members the code has, and how many of them are unique, along with any archives skipped for containing themselves
+fn stats(hasher: &mut FVC2Hasher, cycles: usize) -> String {
+    let members = format!("Members: {} total, {} unique", hasher.len(), hasher.unique_len());
+    match cycles {
+        0 => members,
+        cycles => format!("{}\nArchive cycles: {}", members, cycles)
+    }
+}
+
+// largest_files returns the n largest files that contributed to the given trees, largest first, with ties in path order
+#[cfg(feature = "extract")]
+fn largest_files(collections: &[file_verification_code::archive_tree::Collection], n: usize) -> Vec<(PathBuf, &file_verification_code::archive_tree::File)> {
+    let mut files: Vec<_> = collections.iter().flat_map(file_verification_code::archive_tree::contributing_files).collect();
+    files.sort_by(|(a_path, a), (b_path, b)| b.size.cmp(&a.size).then_with(|| a_path.cmp(b_path)));
+    files.truncate(n);
+
+    files
+}
+
+// duplicate_report describes the archives in the given trees whose members share content, one line per archive and then one per shared sha256
+// Duplicates still count once per member towards the code, this only reports them
+#[cfg(feature = "extract")]
+fn duplicate_report(collections: &[file_verification_code::archive_tree::Collection]) -> String {
+    let mut report = String::new();
+    for (archive, members) in collections.iter().flat_map(file_verification_code::archive_tree::archive_duplicates) {
+        let duplicated: usize = members.values().map(|paths| paths.len()).sum();
+        report.push_str(&format!("{}: {} members share their content with another member\n", archive.display(), duplicated));
+        for (sha256, paths) in members {
+            let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+            report.push_str(&format!("\t{}\t{}\n", hex::encode(sha256), paths.join("\t")));
+        }
+    }
+
+    report
+}
+
+// label names the kind of code calculated, so variants of FVC2 are clearly labeled as such
+fn label(cli: &CLI) -> String {
+    let mut variants = Vec::new();
+    if let Some(namespace) = &cli.namespace {
+        variants.push(format!("namespace {:?}", namespace));
+    }
+    if let Some(build_id) = &cli.build_id {
+        variants.push(format!("build id {:?}", build_id));
+    }
+    if cli.metadata_only {
+        variants.push("metadata only".to_string());
+    }
+
+    match variants.is_empty() {
+        true => "FVC".to_string(),
+        false => format!("FVC ({})", variants.join(", "))
+    }
+}
+
+// print_hex writes the hex code to stdout, with the "<label>: " banner written to the given banner destination
+fn print_hex(label: &str, hex: &str, banner_to: BannerDestination, stdout: &mut impl Write, stderr: &mut impl Write) -> std::io::Result<()> {
+    match banner_to {
+        BannerDestination::Stdout => write!(stdout, "{}: ", label)?,
+        BannerDestination::Stderr => {
+            write!(stderr, "{}: ", label)?;
+            stderr.flush()?;
+        },
+        BannerDestination::None => ()
+    };
+    writeln!(stdout, "{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "4656433200ad460448a5947428e2c3e98adfe45915d71f7a4b399910fed1022cc4e1cdc374";
+
+    #[test]
+    fn banner_destinations() {
+        for (banner_to, expected_stdout, expected_stderr) in [
+            (BannerDestination::Stdout, format!("FVC: {}\n", CODE), ""),
+            (BannerDestination::Stderr, format!("{}\n", CODE), "FVC: "),
+            (BannerDestination::None, format!("{}\n", CODE), ""),
+        ] {
+            let mut stdout = Vec::new();
+            let mut stderr = Vec::new();
+            print_hex("FVC", CODE, banner_to, &mut stdout, &mut stderr).expect("printing hex");
+            assert_eq!(String::from_utf8(stdout).unwrap(), expected_stdout, "stdout for {:?}", banner_to);
+            assert_eq!(String::from_utf8(stderr).unwrap(), expected_stderr, "stderr for {:?}", banner_to);
+        }
+    }
+
+    #[test]
+    fn sidecar_per_input() {
+        let tmp = tempdir::TempDir::new("fvc_sidecar").expect("creating temporary directory");
+        let directory = tmp.path().join("foo_bar");
+        std::fs::create_dir(&directory).unwrap();
+        for name in ["foo.txt", "bar.txt"] {
+            std::fs::copy(PathBuf::from("test_data/flat_files").join(name), directory.join(name)).unwrap();
+        }
+        let zap = tmp.path().join("zap.txt");
+        std::fs::copy("test_data/flat_files/zap.txt", &zap).unwrap();
+
+        let cli = CLI::parse_from(["fvc".into(), "--sidecar".into(), format!("{}/", directory.display()), zap.display().to_string()]);
+        let processor = process::new(cli.extract, ProcessOptions::default());
+        let mut hasher = new_hasher(&cli);
+        write_sidecars(&code_per_input(&processor, &cli, &mut hasher).expect("processing inputs")).expect("writing sidecars");
+
+        let code_of = |input: &PathBuf| {
+            let mut hasher = FVC2Hasher::new();
+            processor.calculate_fvc(&mut hasher, std::slice::from_ref(input)).expect("processing input");
+            hasher.hex()
+        };
+        assert_eq!(std::fs::read_to_string(tmp.path().join("foo_bar.fvc")).expect("reading directory sidecar"), code_of(&directory));
+        assert_eq!(std::fs::read_to_string(tmp.path().join("zap.txt.fvc")).expect("reading file sidecar"), code_of(&zap));
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+
+    #[test]
+    fn json_per_input() {
+        let inputs = [PathBuf::from("test_data/flat_files"), PathBuf::from("test_data/flat_archive.tar.gz")];
+        let cli = CLI::parse_from(["fvc", "--format", "json", "test_data/flat_files", "test_data/flat_archive.tar.gz"]);
+        let processor = process::new(cli.extract, ProcessOptions::default());
+        let mut hasher = new_hasher(&cli);
+        let json = serde_json::to_string(&code_per_input(&processor, &cli, &mut hasher).expect("processing inputs")).unwrap();
+
+        let codes: Vec<serde_json::Value> = serde_json::from_str(&json).expect("parsing json");
+        assert_eq!(codes.len(), inputs.len());
+        for (code, input) in codes.iter().zip(inputs.iter()) {
+            let mut alone = FVC2Hasher::new();
+            processor.calculate_fvc(&mut alone, std::slice::from_ref(input)).expect("processing input");
+            assert_eq!(code["input"], input.to_str().unwrap());
+            assert_eq!(code["fvc"], alone.hex());
+        }
+        assert_eq!(codes[0]["file_count"], 3);
+    }
+
+    #[test]
+    fn stats_count_duplicates() {
+        let tmp = tempdir::TempDir::new("fvc_stats").expect("creating temporary directory");
+        for (name, content) in [("foo.txt", "foo\n"), ("foo_copy.txt", "foo\n"), ("bar.txt", "bar\n")] {
+            std::fs::write(tmp.path().join(name), content).unwrap();
+        }
+
+        let mut hasher = FVC2Hasher::new();
+        process::new(process::default_policy(), ProcessOptions::default()).calculate_fvc(&mut hasher, &[tmp.path().to_path_buf()]).expect("processing files");
+        assert!(hasher.unique_len() < hasher.len());
+        assert_eq!(stats(&mut hasher, 0), "Members: 3 total, 2 unique");
+        assert_eq!(stats(&mut hasher, 2), "Members: 3 total, 2 unique\nArchive cycles: 2");
+    }
+
+    #[cfg(feature = "extract")]
+    #[test]
+    fn manifest_hash_is_sha256_of_tree() {
+        use sha2::{Sha256, Digest};
+
+        let processor = process::new(process::default_policy(), ProcessOptions::default());
+        let collections = processor.collect(&[PathBuf::from("test_data/flat_files")]).expect("processing files");
+        let tmp = tempdir::TempDir::new("fvc_manifest_hash").expect("creating temporary directory");
+        let tree = tmp.path().join("tree.json");
+
+        let sha256 = write_tree(&tree, &collections).expect("writing tree");
+        assert_eq!(hex::encode(sha256), hex::encode(Sha256::digest(std::fs::read(&tree).unwrap())));
+    }
+
+    #[cfg(feature = "extract")]
+    #[test]
+    fn top_file_is_largest() {
+        let processor = process::new(ExtractPolicy::None, ProcessOptions::default());
+        let collections = processor.collect(&[PathBuf::from("test_data")]).expect("processing test data");
+        let largest = process::walk("test_data").into_iter()
+            .map(|entry| entry.expect("walking test data"))
+            .filter(|entry| entry.file_type().is_file())
+            .max_by_key(|entry| entry.metadata().unwrap().len())
+            .unwrap();
+
+        let top = largest_files(&collections, 3);
+        assert_eq!(top.len(), 3);
+        assert_eq!(top[0].0, largest.path());
+        assert_eq!(top[0].1.size, largest.metadata().unwrap().len());
+        assert!(top[1].1.size <= top[0].1.size && top[2].1.size <= top[1].1.size);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_to_pipe() {
+        use std::io::Read;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0, "creating pipe");
+        let (mut reader, writer) = (unsafe { std::fs::File::from_raw_fd(fds[0]) }, fds[1]);
+        let cli = CLI::parse_from(["fvc".to_string(), "--output-fd".to_string(), writer.to_string(), "test_data/flat_files".to_string()]);
+        let mut hasher = new_hasher(&cli);
+        process::new(cli.extract, ProcessOptions::default()).calculate_fvc(&mut hasher, &cli.files[..]).expect("processing files");
+        write_to_fd(cli.output_fd.unwrap(), &hasher.sum()).expect("writing to pipe");
+
+        let mut code = Vec::new();
+        reader.read_to_end(&mut code).expect("reading pipe");
+        assert_eq!(hex::encode(code), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+
+        // stdout is never taken over, nor is a descriptor that is not open
+        assert_eq!(write_to_fd(1, b"code").unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(write_to_fd(i32::MAX, b"code").unwrap_err().raw_os_error(), Some(libc::EBADF));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn canonicalize_dedups_symlinked_input() {
+        let tmp = tempdir::TempDir::new("fvc_canonicalize").expect("creating temporary directory");
+        let link = tmp.path().join("alias.txt");
+        std::os::unix::fs::symlink(std::fs::canonicalize("test_data/flat_files/foo.txt").unwrap(), &link).expect("creating symlink");
+        let fvc = |files: &[PathBuf]| {
+            let mut hasher = FVC2Hasher::new();
+            process::new(process::default_policy(), ProcessOptions::default()).calculate_fvc(&mut hasher, files).expect("processing files");
+            hasher.hex()
+        };
+
+        let files = [PathBuf::from("test_data/flat_files/foo.txt"), link];
+        let canonical = canonical_inputs(&files).expect("canonicalizing files");
+        assert_eq!(canonical.len(), 1);
+        assert_eq!(fvc(&canonical), fvc(&files[..1]));
+        assert_ne!(fvc(&files), fvc(&files[..1]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn canonicalize_dedups_case_variants() {
+        let tmp = tempdir::TempDir::new("fvc_canonicalize_case").expect("creating temporary directory");
+        let upper = tmp.path().join("Foo.txt");
+        let lower = tmp.path().join("foo.txt");
+        std::fs::write(&upper, "foo\n").expect("writing test file");
+        // on a case-sensitive filesystem, a hard link stands in for the second name of the same file
+        if !lower.exists() {
+            std::fs::hard_link(&upper, &lower).expect("linking test file");
+        }
+
+        let canonical = canonical_inputs(&[upper.clone(), lower]).expect("canonicalizing files");
+        assert_eq!(canonical, vec![std::fs::canonicalize(&upper).unwrap()]);
+
+        // distinct files with the same content are both kept
+        let copy = tmp.path().join("copy.txt");
+        std::fs::write(&copy, "foo\n").expect("writing test file");
+        assert_eq!(canonical_inputs(&[upper, copy]).expect("canonicalizing files").len(), 2);
+    }
+
+    #[test]
+    fn uppercase_hex() {
+        let code = std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap();
+        for (uppercase, expected) in [(false, code.trim().to_string()), (true, code.trim().to_uppercase())] {
+            let cli = CLI::parse_from(["fvc", "--uppercase", "test_data/flat_files"].into_iter().filter(|arg| uppercase || *arg != "--uppercase"));
+            let mut hasher = new_hasher(&cli);
+            process::new(cli.extract, ProcessOptions::default()).calculate_fvc(&mut hasher, &cli.files[..]).expect("processing files");
+            assert_eq!(hex_of(&cli, &mut hasher), expected);
+        }
+    }
+
+    #[test]
+    fn all_versions_from_same_members() {
+        use sha2::Digest;
+
+        // FVC2 is the only version yet, so a second one is mocked: sha256 of the FVC2 digest under an FVC9 prefix
+        let fvc9: file_verification_code::VersionFold = |hasher| {
+            let mut code = b"FVC9\0".to_vec();
+            code.extend_from_slice(&sha2::Sha256::digest(hasher.digest()));
+            code
+        };
+        let cli = CLI::parse_from(["fvc", "--all-versions", "test_data/flat_files"]);
+        let mut hasher = new_hasher(&cli);
+        process::new(cli.extract, ProcessOptions::default()).calculate_fvc(&mut hasher, &cli.files[..]).expect("processing files");
+        let lines = all_versions(&cli, &mut hasher, &[file_verification_code::SUPPORTED_VERSIONS[0], (9, fvc9)]);
+
+        let fvc2 = std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap();
+        let digest: [u8; 32] = hex::decode(&fvc2.trim()[10..]).unwrap().try_into().unwrap();
+        let expected = format!("FVC2: {}\nFVC9: {}{}\n", fvc2.trim(), hex::encode(b"FVC9\0"), hex::encode(sha2::Sha256::digest(digest)));
+        assert_eq!(lines, expected);
+    }
+
+    #[cfg(feature = "extract")]
+    #[test]
+    fn archive_duplicates_are_reported() {
+        // duplicate_members.tar.gz holds foo.txt twice, as foo.txt and docs/foo-copy.txt, and bar.txt once
+        let cli = CLI::parse_from(["fvc", "--archive-duplicates", "test_data/duplicate_members.tar.gz"]);
+        let collections = process::new(cli.extract, ProcessOptions::default()).collect(&cli.files[..]).expect("processing archive");
+        let foo = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(b"foo\n"));
+        assert_eq!(duplicate_report(&collections), format!("duplicate_members.tar.gz: 2 members share their content with another member\n\t{}\tdocs/foo-copy.txt\tfoo.txt\n", foo));
+
+        assert_eq!(duplicate_report(&process::new(cli.extract, ProcessOptions::default()).collect(&[PathBuf::from("test_data/flat_archive.tar.gz")]).unwrap()), "");
+    }
+
+    #[test]
+    fn verify_stable_detects_changed_files() {
+        let tmp = tempdir::TempDir::new("fvc_verify_stable").expect("creating temporary directory");
+        for name in ["foo.txt", "bar.txt", "zap.txt"] {
+            std::fs::copy(PathBuf::from("test_data/flat_files").join(name), tmp.path().join(name)).unwrap();
+        }
+        let cli = CLI::parse_from(["fvc".to_string(), "--verify-stable".to_string(), tmp.path().display().to_string()]);
+        let mut hasher = new_hasher(&cli);
+        process::new(cli.extract, ProcessOptions::default()).calculate_fvc(&mut hasher, &cli.files[..]).expect("processing files");
+        let first = hasher.hex();
+
+        verify_stable(&cli, &ProcessOptions::default(), &first).expect("verifying an unchanged tree");
+
+        // as if a file was rewritten between the two passes
+        std::fs::write(tmp.path().join("zap.txt"), "changed\n").unwrap();
+        let err = verify_stable(&cli, &ProcessOptions::default(), &first).expect_err("verifying a changed tree");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(&first));
+    }
+
+    #[test]
+    fn ignore_extension_extends_generated() {
+        let cli = CLI::parse_from(["fvc", "--ignore-generated", "--ignore-extension", ".log", "test_data/flat_files"]);
+        let extensions = ignored_extensions(&cli);
+        assert!(extensions.iter().any(|extension| extension == "pyc"));
+        assert!(extensions.iter().any(|extension| extension == "log"));
+
+        let cli = CLI::parse_from(["fvc", "--ignore-extension", "log", "test_data/flat_files"]);
+        assert_eq!(ignored_extensions(&cli), vec!["log".to_string()]);
+    }
+}
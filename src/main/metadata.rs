@@ -0,0 +1,103 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Codes of file metadata alone, for change detection where reading content is too expensive
+//! Every file contributes its path relative to the given input, its size and its modification time, so nothing is read and no archive is extracted
+
+use crate::process::{walk, ProcessOptions};
+use file_verification_code::FVC2Hasher;
+
+use std::path::{Path, PathBuf};
+use log::*;
+
+/// hash_metadata adds the metadata of every file under the given inputs to hasher
+pub fn hash_metadata(hasher: &mut FVC2Hasher, files: &[PathBuf], options: &ProcessOptions) -> std::io::Result<()> {
+    for input in files {
+        for entry in walk(input).into_iter().filter_entry(|entry| !options.is_excluded(entry)) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            options.check_cancelled()?;
+
+            // a file given directly is named by its file name, files found in a directory by their path under it
+            let relative = match entry.depth() {
+                0 => entry.path().file_name().map(Path::new).unwrap_or(entry.path()),
+                _ => entry.path().strip_prefix(input).unwrap_or(entry.path())
+            };
+            // with strip_components, a wrapper directory such as project-1.2.3/ is left out, and files no deeper than it are skipped
+            let relative: PathBuf = relative.components().skip(options.strip_components).collect();
+            if relative.as_os_str().is_empty() {
+                debug!("skipping {}, it has no more than {} components", entry.path().display(), options.strip_components);
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            trace!("adding metadata of {}", entry.path().display());
+            hasher.read_metadata(&relative, metadata.len(), metadata.modified()?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{self, Processor};
+    use file_verification_code::FVCHasher;
+
+    #[test]
+    fn mtime_changes_metadata_code_only() {
+        let tmp = tempdir::TempDir::new("fvc_metadata").expect("creating temporary directory");
+        for name in ["foo.txt", "bar.txt", "zap.txt"] {
+            std::fs::copy(Path::new("test_data/flat_files").join(name), tmp.path().join(name)).unwrap();
+        }
+        let files = [tmp.path().to_path_buf()];
+        let codes = || {
+            let mut metadata = FVC2Hasher::new();
+            hash_metadata(&mut metadata, &files, &ProcessOptions::default()).expect("hashing metadata");
+            let mut content = FVC2Hasher::new();
+            process::new(process::default_policy(), ProcessOptions::default()).calculate_fvc(&mut content, &files).expect("processing files");
+            (metadata.hex(), content.hex())
+        };
+
+        let (metadata_before, content_before) = codes();
+        let touched = std::fs::File::options().write(true).open(tmp.path().join("foo.txt")).unwrap();
+        touched.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000)).expect("touching file");
+        let (metadata_after, content_after) = codes();
+
+        assert_ne!(metadata_before, metadata_after);
+        assert_eq!(content_before, content_after);
+        assert_eq!(content_after, std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+
+    #[test]
+    fn strip_components_ignores_wrapper_directory() {
+        let tmp = tempdir::TempDir::new("fvc_metadata").expect("creating temporary directory");
+        let releases = [tmp.path().join("a"), tmp.path().join("b")];
+        for (release, wrapper) in releases.iter().zip(["project-1.2.3", "project-1.2.4"]) {
+            std::fs::create_dir_all(release.join(wrapper)).unwrap();
+            for name in ["foo.txt", "bar.txt", "zap.txt"] {
+                let path = release.join(wrapper).join(name);
+                std::fs::copy(Path::new("test_data/flat_files").join(name), &path).unwrap();
+                let file = std::fs::File::options().write(true).open(&path).unwrap();
+                file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000)).expect("touching file");
+            }
+        }
+        let code = |release: &Path, strip_components: usize| {
+            let mut hasher = FVC2Hasher::new();
+            hash_metadata(&mut hasher, &[release.to_path_buf()], &ProcessOptions { strip_components, ..Default::default() }).expect("hashing metadata");
+            hasher.hex()
+        };
+
+        assert_ne!(code(&releases[0], 0), code(&releases[1], 0));
+        assert_eq!(code(&releases[0], 1), code(&releases[1], 1));
+    }
+}
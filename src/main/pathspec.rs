@@ -0,0 +1,91 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Select the files of a git repository to process with git pathspecs, for the code of a subsystem defined by git rules
+//! Only files tracked in the repository's index are selected, so untracked and ignored files never contribute
+
+use std::path::{Path, PathBuf};
+use git2::{Pathspec, PathspecFlags, Repository};
+use log::*;
+
+/// tracked_files replaces every input with the files tracked in its git repository that are under it and match pathspec
+/// Pathspecs are matched against paths relative to the root of the repository's working tree, as git does
+pub fn tracked_files(inputs: &[PathBuf], pathspec: &[String]) -> std::io::Result<Vec<PathBuf>> {
+    let pathspec = Pathspec::new(pathspec.iter()).map_err(git_error)?;
+    let mut files = Vec::new();
+    for input in inputs {
+        let input = std::fs::canonicalize(input)?;
+        let repository = Repository::discover(&input).map_err(git_error)?;
+        let workdir = match repository.workdir() {
+            Some(workdir) => std::fs::canonicalize(workdir)?,
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is in a bare repository", input.display())))
+        };
+        let prefix = input.strip_prefix(&workdir).expect("an input is under the working tree of its repository");
+
+        let index = repository.index().map_err(git_error)?;
+        let matches = pathspec.match_index(&index, PathspecFlags::DEFAULT).map_err(git_error)?;
+        let before = files.len();
+        for entry in matches.entries() {
+            let path = Path::new(std::str::from_utf8(entry).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?);
+            if path.starts_with(prefix) {
+                files.push(workdir.join(path));
+            }
+        }
+        debug!("{} tracked files under {} match the pathspec", files.len() - before, input.display());
+    }
+
+    Ok(files)
+}
+
+// git_error converts an error from git into an io::Error
+fn git_error(err: git2::Error) -> std::io::Error {
+    std::io::Error::other(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{self, Processor, ProcessOptions};
+    use file_verification_code::{FVCHasher, FVCSha256Hasher, FVC2Hasher};
+
+    #[test]
+    fn pathspec_selects_tracked_subdirectory() {
+        use sha2::{Sha256, Digest};
+
+        let tmp = tempdir::TempDir::new("fvc_pathspec").expect("creating temporary directory");
+        let repository = Repository::init(tmp.path()).expect("initializing repository");
+        for (path, content) in [("src/foo.txt", "foo\n"), ("src/lib/bar.txt", "bar\n"), ("docs/zap.txt", "zap\n")] {
+            let path = tmp.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        let mut index = repository.index().expect("opening index");
+        index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None).expect("adding files");
+        index.write().expect("writing index");
+        // not tracked, so left out even though the pathspec matches it
+        std::fs::write(tmp.path().join("src/untracked.txt"), "untracked\n").unwrap();
+
+        let files = tracked_files(&[tmp.path().to_path_buf()], &["src".to_string()]).expect("matching pathspec");
+        let workdir = std::fs::canonicalize(tmp.path()).unwrap();
+        assert_eq!(files, [workdir.join("src/foo.txt"), workdir.join("src/lib/bar.txt")]);
+
+        let mut hasher = FVC2Hasher::new();
+        process::new(process::default_policy(), ProcessOptions::default()).calculate_fvc(&mut hasher, &files[..]).expect("processing files");
+        let mut expected = FVC2Hasher::new();
+        for content in ["foo\n", "bar\n"] {
+            expected.read_sha256(Sha256::digest(content).into());
+        }
+        assert_eq!(hasher.hex(), expected.hex());
+
+        // inputs narrow the pathspec to the files under them
+        let files = tracked_files(&[tmp.path().join("src/lib")], &["*.txt".to_string()]).expect("matching pathspec");
+        assert_eq!(files, [workdir.join("src/lib/bar.txt")]);
+    }
+}
@@ -9,33 +9,232 @@
 // OR CONDITIONS OF ANY KIND, either express or implied.
 
 use crate::FVC2Hasher;
+use crate::audit::{AuditLog, Decision};
+use file_verification_code::progress::ProgressReporter;
 
-use std::path::PathBuf;
-use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "extract")]
+use std::sync::atomic::AtomicUsize;
+use walkdir::WalkDir;
+
+pub use file_verification_code::policy::ExtractPolicy;
+
+/// ProcessOptions holds the settings, beyond the extraction policy, that change which files a Processor includes
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// skip the metadata directories of version control systems, see VCS_DIRECTORIES
+    pub exclude_vcs: bool,
+    /// skip files with these extensions, such as GENERATED_EXTENSIONS
+    pub ignored_extensions: Vec<String>,
+    /// skip files with these sha256s, such as boilerplate found in many trees, which are hashed before they can be skipped
+    pub excluded_hashes: Vec<[u8; 32]>,
+    /// hash text files with trailing whitespace stripped from every line and the newlines at their end dropped, giving a distinct code
+    pub normalize_whitespace: bool,
+    /// fail on directories with more than this many entries, before walking into them
+    pub max_dir_entries: Option<usize>,
+    /// remove this many leading components from the paths that path-sensitive codes record
+    pub strip_components: usize,
+    /// report hashed files and extracted archives as they are processed
+    pub progress: Option<Arc<dyn ProgressReporter>>,
+    /// record what was done with every file, and why
+    pub audit: Option<AuditLog>,
+    /// stop processing with a Cancelled error once this is set, it is checked before every file and archive
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// record the order entries are stored in on every extracted archive
+    #[cfg(feature = "extract")]
+    pub entry_order: bool,
+    /// skip detecting archives that contain themselves, trusting the archive depth limit instead
+    #[cfg(feature = "extract")]
+    pub no_cycle_detection: bool,
+    /// fail on an archive that contains itself, instead of warning and skipping it
+    #[cfg(feature = "extract")]
+    pub fail_on_cycle: bool,
+    /// counts the archives found to contain themselves, shared by every clone of these options
+    #[cfg(feature = "extract")]
+    pub cycles_detected: Arc<AtomicUsize>,
+    /// record the uncompressed size, and resulting metadata hash, of every extracted archive
+    #[cfg(feature = "extract")]
+    pub uncompressed_size: bool,
+    /// represent archives holding a single file as just that file
+    #[cfg(feature = "extract")]
+    pub flatten_single: bool,
+    /// fail on truncated or corrupt compressed streams, instead of warning and hashing them as files
+    #[cfg(feature = "extract")]
+    pub strict_extract: bool,
+    /// hash archives whose extracted files add up to more than this many bytes as files
+    #[cfg(feature = "extract")]
+    pub max_archive_size: Option<u64>,
+    /// extract only the installed files of Debian packages, leaving out their control archive
+    #[cfg(feature = "extract")]
+    pub package_payload: bool,
+    /// fold the sha256 of every archive file into the code instead of its contents, which are still extracted into the tree
+    #[cfg(feature = "extract")]
+    pub hash_archive_file: bool,
+    /// what symlinks found while walking contribute to the code
+    #[cfg(feature = "extract")]
+    pub symlinks: SymlinkMode,
+    /// skip the metadata that archiving tools add to archives made on macOS and Windows, see ARCHIVE_JUNK
+    #[cfg(feature = "extract")]
+    pub exclude_archive_junk: bool,
+    /// decompress zstd streams with this dictionary before extracting them
+    #[cfg(feature = "zstd")]
+    pub zstd_dictionary: Option<file_verification_code::extract::ZstdDictionary>,
+    /// hash the files of every walked directory in parallel before processing them one by one
+    #[cfg(feature = "parallel")]
+    pub parallel: bool,
+}
+
+/// SymlinkMode is what a symlink found while walking a directory or extracted archive contributes to the code
+#[cfg(feature = "extract")]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SymlinkMode {
+    /// Contribute the sha256 of the target path, as recorded in the tree, relative to the walked directory when inside it
+    HashTarget,
+    /// Contribute the content of the target file, if it is inside the walked directory; links to directories, already walked, add nothing
+    Follow,
+    /// Contribute nothing, the link is only recorded in the tree
+    #[default]
+    Skip
+}
+
+/// Cancelled is the error, wrapped in an std::io::Error of kind Interrupted, returned when processing was cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "processing was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// GENERATED_EXTENSIONS are the extensions of commonly generated files, compiled objects and bytecode, left out of a normalized source FVC
+pub const GENERATED_EXTENSIONS: &[&str] = &["pyc", "pyo", "o", "obj", "lo", "class", "elc"];
+
+// names of the metadata directories kept by well-known version control systems
+const VCS_DIRECTORIES: &[&str] = &[".git", ".svn", ".hg", ".bzr"];
+
+// names of the directories and files that archiving tools on macOS and Windows add next to the actual contents
+#[cfg(feature = "extract")]
+const ARCHIVE_JUNK: &[&str] = &["__MACOSX", ".DS_Store", "Thumbs.db"];
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-pub enum ExtractPolicy {
-    /// Only try to extract files with extensions that look like archives
-    Extension,
-    /// Try to extract every file
-    All,
-    /// Don't extract, treat archives as binary files
-    None    
+impl ProcessOptions {
+    /// is_excluded returns whether a walked entry, and everything under it, should be left out
+    /// The root of a walk is never excluded, so explicitly given paths are always processed
+    pub fn is_excluded(&self, entry: &walkdir::DirEntry) -> bool {
+        if entry.depth() == 0 {
+            return false;
+        }
+
+        let vcs = self.exclude_vcs && VCS_DIRECTORIES.iter().any(|vcs| entry.file_name() == *vcs);
+        let ignored = entry.file_type().is_file() && match entry.path().extension() {
+            Some(extension) => self.ignored_extensions.iter().any(|ignored| extension == ignored.as_str()),
+            None => false
+        };
+
+        vcs || ignored
+    }
+
+    /// is_excluded_hash returns whether a file with the given sha256 should be left out
+    pub fn is_excluded_hash(&self, sha256: &[u8; 32]) -> bool {
+        self.excluded_hashes.contains(sha256)
+    }
+
+    /// is_archive_junk returns whether an entry walked in an extracted archive, and everything under it, should be left out as archiving metadata
+    #[cfg(feature = "extract")]
+    pub fn is_archive_junk(&self, entry: &walkdir::DirEntry) -> bool {
+        self.exclude_archive_junk && entry.depth() > 0 && ARCHIVE_JUNK.iter().any(|junk| entry.file_name() == *junk)
+    }
+
+    /// normalized_sha256 returns the sha256 of the file at path with its whitespace normalized, if normalize_whitespace is set and the file is text
+    /// None means the file is to be hashed as it is
+    pub fn normalized_sha256(&self, path: &Path) -> std::io::Result<Option<[u8; 32]>> {
+        match self.normalize_whitespace {
+            true => file_verification_code::digest::sha256_of_normalized_text(std::fs::File::open(path)?),
+            false => Ok(None)
+        }
+    }
+
+    /// check_dir_entries returns an error if dir has more than max_dir_entries entries
+    /// The entries are only counted, so a pathological directory is refused without ever holding all of its entries
+    pub fn check_dir_entries(&self, dir: &Path) -> std::io::Result<()> {
+        let max_dir_entries = match self.max_dir_entries {
+            Some(max_dir_entries) => max_dir_entries,
+            None => return Ok(())
+        };
+
+        let mut entries = 0;
+        for entry in std::fs::read_dir(dir)? {
+            entry?;
+            entries += 1;
+            if entries > max_dir_entries {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} has more than the maximum of {} entries", dir.display(), max_dir_entries)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// check_cancelled returns a Cancelled error if processing was asked to stop
+    pub fn check_cancelled(&self) -> std::io::Result<()> {
+        match &self.cancel {
+            Some(cancel) if cancel.load(Ordering::Relaxed) => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, Cancelled)),
+            _ => Ok(())
+        }
+    }
+
+    /// on_file reports a hashed file to the progress reporter, if there is one
+    pub fn on_file(&self, path: &Path, sha256: &[u8; 32], size: u64) {
+        if let Some(progress) = &self.progress {
+            progress.on_file(path, sha256, size);
+        }
+    }
+
+    /// on_archive reports an extracted archive to the progress reporter, if there is one
+    #[cfg(feature = "extract")]
+    pub fn on_archive(&self, path: &Path, sha256: &[u8; 32]) {
+        if let Some(progress) = &self.progress {
+            progress.on_archive(path, sha256);
+        }
+    }
+
+    /// audit records the decision made for path in the audit log, if there is one
+    pub fn audit(&self, path: &Path, decision: Decision, reason: &str) {
+        if let Some(audit) = &self.audit {
+            audit.record(path, decision, reason);
+        }
+    }
+
+    /// audit_err records that processing path failed with err, and passes err on
+    pub fn audit_err(&self, path: &Path, err: std::io::Error) -> std::io::Error {
+        self.audit(path, Decision::Failed, &err.to_string());
+        err
+    }
 }
 
 pub trait Processor {
-    fn new(extract_policy: ExtractPolicy) -> Self;
+    fn new(extract_policy: ExtractPolicy, options: ProcessOptions) -> Self;
     /// calculate_fvc iterates over the given files and adds them to the FVCHasher, or extracts and/or walk given archives/directories and does the same for their files.
     /// The actual fvc at the end can be obtained from the given hasher.
     fn calculate_fvc(self: &Self, hasher: &mut FVC2Hasher, files: &[PathBuf]) -> std::io::Result<()>;
 }
 
+/// walk returns a WalkDir over path that yields entries in a stable, locale-independent order.
+/// Siblings are sorted by the raw bytes of their file names (OsStr comparison), never by locale collation,
+/// so the walk order, and anything built from it, is the same on every system regardless of `LC_COLLATE`.
+pub fn walk<P: AsRef<Path>>(path: P) -> WalkDir {
+    WalkDir::new(path).sort_by_file_name()
+}
+
 // use ExtractionProcessor if feature enabled
 #[cfg(feature = "extract")]
 mod process_extract;
 #[cfg(feature = "extract")]
-pub fn new(extract_policy: ExtractPolicy) -> process_extract::ExtractionProcessor {
-    process_extract::ExtractionProcessor::new(extract_policy)
+pub fn new(extract_policy: ExtractPolicy, options: ProcessOptions) -> process_extract::ExtractionProcessor {
+    process_extract::ExtractionProcessor::new(extract_policy, options)
 }
 #[cfg(feature = "extract")]
 pub fn default_policy() -> ExtractPolicy {
@@ -46,10 +245,221 @@ pub fn default_policy() -> ExtractPolicy {
 #[cfg(not(feature = "extract"))]
 mod process;
 #[cfg(not(feature = "extract"))]
-pub fn new(extract_policy: ExtractPolicy) -> process::SimpleProcessor {
-    process::SimpleProcessor::new(extract_policy)
+fn process_file<P: AsRef<Path>>(hasher: &mut FVC2Hasher, path: P, options: &ProcessOptions) -> std::io::Result<()> {
+    use file_verification_code::FVCSha256Hasher;
+    use sha2::{Sha256, Digest};
+
+    options.check_cancelled()?;
+    log::info!("Adding file \"{}\"", path.as_ref().display());
+    let mut file = std::fs::File::open(&path).map_err(|err| options.audit_err(path.as_ref(), err))?;
+    let mut sha256 = Sha256::new();
+    let size = std::io::copy(&mut file, &mut sha256).map_err(|err| options.audit_err(path.as_ref(), err))?;
+    let sha256: [u8; 32] = match options.normalized_sha256(path.as_ref()).map_err(|err| options.audit_err(path.as_ref(), err))? {
+        Some(normalized) => normalized,
+        None => sha256.finalize().into()
+    };
+    if options.is_excluded_hash(&sha256) {
+        options.audit(path.as_ref(), Decision::Skipped, "excluded by hash");
+        return Ok(());
+    }
+    hasher.read_sha256(sha256);
+
+    options.audit(path.as_ref(), Decision::Hashed, "extraction is not supported in this build");
+    options.on_file(path.as_ref(), &sha256, size);
+    Ok(())
+}
+#[cfg(not(feature = "extract"))]
+pub fn new(extract_policy: ExtractPolicy, options: ProcessOptions) -> process::SimpleProcessor {
+    process::SimpleProcessor::new(extract_policy, options)
 }
 #[cfg(not(feature = "extract"))]
 pub fn default_policy() -> ExtractPolicy {
     ExtractPolicy::None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_sorts_by_raw_bytes() {
+        // under a non-C locale such as en_US.UTF-8 these collate as "a.txt", "Ä.txt", "B.txt", "_c.txt" (or with "_c.txt" first)
+        let names = ["a.txt", "_c.txt", "\u{00c4}.txt", "B.txt"];
+        let tmp = tempdir::TempDir::new("fvc_walk_order").expect("creating temporary directory");
+        for name in names.iter() {
+            std::fs::write(tmp.path().join(name), name).expect("writing test file");
+        }
+
+        let walked: Vec<String> = walk(tmp.path()).min_depth(1).into_iter()
+            .map(|entry| entry.expect("walking directory").file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(walked, vec!["B.txt", "_c.txt", "a.txt", "\u{00c4}.txt"]);
+    }
+
+    #[test]
+    fn exclude_vcs_ignores_git_directory() {
+        use file_verification_code::FVCHasher;
+
+        let tmp = tempdir::TempDir::new("fvc_exclude_vcs").expect("creating temporary directory");
+        std::fs::write(tmp.path().join("a.txt"), "a\n").expect("writing test file");
+        let mut clean = FVC2Hasher::new();
+        new(default_policy(), ProcessOptions { exclude_vcs: true, ..Default::default() }).calculate_fvc(&mut clean, &[tmp.path().to_path_buf()]).expect("processing clean tree");
+
+        std::fs::create_dir(tmp.path().join(".git")).expect("creating .git");
+        std::fs::write(tmp.path().join(".git").join("HEAD"), "ref: refs/heads/main\n").expect("writing .git/HEAD");
+        let mut excluded = FVC2Hasher::new();
+        new(default_policy(), ProcessOptions { exclude_vcs: true, ..Default::default() }).calculate_fvc(&mut excluded, &[tmp.path().to_path_buf()]).expect("processing tree with .git");
+        let mut included = FVC2Hasher::new();
+        new(default_policy(), ProcessOptions::default()).calculate_fvc(&mut included, &[tmp.path().to_path_buf()]).expect("processing tree with .git");
+
+        assert_eq!(excluded.hex(), clean.hex());
+        assert_ne!(included.hex(), clean.hex());
+    }
+
+    #[test]
+    fn generated_files_are_ignored() {
+        use file_verification_code::FVCHasher;
+
+        let tmp = tempdir::TempDir::new("fvc_ignore_generated").expect("creating temporary directory");
+        std::fs::write(tmp.path().join("foo.py"), "print('foo')\n").expect("writing test file");
+        let fvc = |options: ProcessOptions| {
+            let mut hasher = FVC2Hasher::new();
+            new(default_policy(), options).calculate_fvc(&mut hasher, &[tmp.path().to_path_buf()]).expect("processing tree");
+            hasher.hex()
+        };
+        let source = fvc(ProcessOptions::default());
+
+        std::fs::write(tmp.path().join("foo.pyc"), [0x42, 0x0d, 0x0d, 0x0a]).expect("writing generated file");
+        let generated = ProcessOptions { ignored_extensions: GENERATED_EXTENSIONS.iter().map(|extension| extension.to_string()).collect(), ..Default::default() };
+        assert_eq!(fvc(generated), source);
+        assert_ne!(fvc(ProcessOptions::default()), source);
+    }
+
+    #[test]
+    fn excluded_hash_is_skipped() {
+        use file_verification_code::FVCHasher;
+        use sha2::{Sha256, Digest};
+
+        let tmp = tempdir::TempDir::new("fvc_exclude_hash").expect("creating temporary directory");
+        std::fs::write(tmp.path().join("main.c"), "int main() { return 0; }\n").expect("writing test file");
+        let fvc = |options: ProcessOptions| {
+            let mut hasher = FVC2Hasher::new();
+            new(default_policy(), options).calculate_fvc(&mut hasher, &[tmp.path().to_path_buf()]).expect("processing tree");
+            hasher.hex()
+        };
+        let source = fvc(ProcessOptions::default());
+
+        let license = "Licensed under the Apache License, Version 2.0\n";
+        std::fs::write(tmp.path().join("LICENSE"), license).expect("writing license");
+        let excluded = ProcessOptions { excluded_hashes: vec![Sha256::digest(license).into()], ..Default::default() };
+        assert_eq!(fvc(excluded), source);
+        assert_ne!(fvc(ProcessOptions::default()), source);
+    }
+
+    #[test]
+    fn normalize_whitespace_ignores_trailing_whitespace() {
+        use file_verification_code::FVCHasher;
+
+        let tmp = tempdir::TempDir::new("fvc_normalize_whitespace").expect("creating temporary directory");
+        let trimmed = tmp.path().join("trimmed.txt");
+        let padded = tmp.path().join("padded.txt");
+        std::fs::write(&trimmed, "fn main() {\n    println!();\n}").expect("writing test file");
+        std::fs::write(&padded, "fn main() { \n    println!();\t\n}\n\n").expect("writing test file");
+        let code = |path: &PathBuf, normalize_whitespace: bool| {
+            let mut hasher = FVC2Hasher::new();
+            let options = ProcessOptions { normalize_whitespace, ..Default::default() };
+            new(default_policy(), options).calculate_fvc(&mut hasher, std::slice::from_ref(path)).expect("processing file");
+            hasher.hex()
+        };
+
+        assert_eq!(code(&trimmed, true), code(&padded, true));
+        assert_ne!(code(&trimmed, false), code(&padded, false));
+        // text that is already normalized hashes as it is
+        assert_eq!(code(&trimmed, true), code(&trimmed, false));
+    }
+
+    #[test]
+    fn max_dir_entries_is_enforced() {
+        let tmp = tempdir::TempDir::new("fvc_max_dir_entries").expect("creating temporary directory");
+        let crowded = tmp.path().join("crowded");
+        std::fs::create_dir(&crowded).expect("creating directory");
+        for index in 0..5 {
+            std::fs::write(crowded.join(format!("{}.txt", index)), index.to_string()).expect("writing test file");
+        }
+        let process = |max_dir_entries: usize| {
+            let options = ProcessOptions { max_dir_entries: Some(max_dir_entries), ..Default::default() };
+            new(default_policy(), options).calculate_fvc(&mut FVC2Hasher::new(), &[tmp.path().to_path_buf()])
+        };
+
+        // the root holds one entry, the nested directory five
+        assert!(process(5).is_ok());
+        let err = process(4).expect_err("crowded directory should be refused");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("crowded"), "{}", err);
+    }
+
+    #[test]
+    fn progress_reporter_is_called() {
+        use std::sync::atomic::AtomicUsize;
+        use file_verification_code::FVCHasher;
+
+        #[derive(Default)]
+        struct Counts { files: AtomicUsize, archives: AtomicUsize, done: AtomicUsize }
+        impl ProgressReporter for Counts {
+            fn on_file(&self, _path: &Path, _sha256: &[u8; 32], _size: u64) {
+                self.files.fetch_add(1, Ordering::Relaxed);
+            }
+            fn on_archive(&self, _path: &Path, _sha256: &[u8; 32]) {
+                self.archives.fetch_add(1, Ordering::Relaxed);
+            }
+            fn on_done(&self, _fvc: &str) {
+                self.done.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let counts = Arc::new(Counts::default());
+        let options = ProcessOptions { progress: Some(counts.clone()), ..Default::default() };
+        let mut hasher = FVC2Hasher::new();
+        new(default_policy(), options).calculate_fvc(&mut hasher, &[PathBuf::from("test_data/flat_files"), PathBuf::from("test_data/flat_archive.tar")]).expect("processing files");
+        counts.on_done(&hasher.hex());
+
+        // the archive's three files are reported along with the archive, or it is a file itself without extraction
+        #[cfg(feature = "extract")]
+        let (files, archives) = (6, 1);
+        #[cfg(not(feature = "extract"))]
+        let (files, archives) = (4, 0);
+        assert_eq!(counts.files.load(Ordering::Relaxed), files);
+        assert_eq!(counts.archives.load(Ordering::Relaxed), archives);
+        assert_eq!(counts.done.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn cancel_stops_after_first_file() {
+        // CancelOnWrite sets the cancel flag as soon as the first event is written
+        struct CancelOnWrite(Arc<AtomicBool>, Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for CancelOnWrite {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.store(true, Ordering::Relaxed);
+                self.1.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let written = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let options = ProcessOptions {
+            progress: Some(Arc::new(crate::events::EventLog::from_writer(CancelOnWrite(cancel.clone(), written.clone())))),
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+
+        let mut hasher = FVC2Hasher::new();
+        let err = new(default_policy(), options).calculate_fvc(&mut hasher, &[PathBuf::from("test_data/flat_files")]).expect_err("processing should be cancelled");
+        assert!(err.get_ref().is_some_and(|inner| inner.is::<Cancelled>()), "unexpected error: {}", err);
+        let events = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert_eq!(events.lines().count(), 1, "events: {}", events);
+    }
+}
@@ -12,20 +12,22 @@
 //! Archives are treated as files
 
 use crate::FVC2Hasher;
-use super::{ExtractPolicy, Processor, process_file};
+use super::{ExtractPolicy, ProcessOptions, Processor, process_file, walk};
+use crate::audit::Decision;
 
 use std::path::PathBuf;
 
-use walkdir::WalkDir;
 use std::fs::metadata;
 use log::info;
 
 
-pub struct SimpleProcessor {}
+pub struct SimpleProcessor {
+    options: ProcessOptions,
+}
 impl Processor for SimpleProcessor {
-    fn new(extract_policy: ExtractPolicy) -> Self {
+    fn new(extract_policy: ExtractPolicy, options: ProcessOptions) -> Self {
         assert_eq!(extract_policy, ExtractPolicy::None);
-        Self {  }
+        Self { options }
     }
 
     fn calculate_fvc(self: &Self, hasher: &mut FVC2Hasher, files: &[PathBuf]) -> std::io::Result<()> {
@@ -38,7 +40,7 @@ impl Processor for SimpleProcessor {
             };
     
             if stat.is_file() {
-                match process_file(hasher, path) {
+                match process_file(hasher, path, &self.options) {
                     Ok(()) => (),
                     Err(err) => {
                         return Err(err);
@@ -47,7 +49,14 @@ impl Processor for SimpleProcessor {
             } else if stat.is_dir() {
                 info!("Adding directory \"{}\"", path.display());
     
-                for entry in WalkDir::new(path) {
+                let excluded = |entry: &walkdir::DirEntry| match self.options.is_excluded(entry) {
+                    true => {
+                        self.options.audit(entry.path(), Decision::Skipped, "excluded");
+                        true
+                    },
+                    false => false
+                };
+                for entry in walk(path).into_iter().filter_entry(|entry| !excluded(entry)) {
                     let entry = match entry {
                         Ok(dir_entry) => dir_entry,
                         Err(err) => {
@@ -55,9 +64,13 @@ impl Processor for SimpleProcessor {
                         }
                     };
     
+                    if entry.file_type().is_dir() {
+                        self.options.check_dir_entries(entry.path())?;
+                    }
+
                     // only process files
                     if entry.file_type().is_file() {
-                        match process_file(hasher, entry.path()) {
+                        match process_file(hasher, entry.path(), &self.options) {
                             Ok(()) => (),
                             Err(err) => {
                                 return Err(err);
@@ -67,6 +80,7 @@ impl Processor for SimpleProcessor {
                 }
             } else {
                 info!("Skipping irregular file {}", path.display());
+                self.options.audit(path, Decision::Skipped, "not a regular file");
             }
         }
     
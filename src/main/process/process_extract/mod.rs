@@ -11,7 +11,8 @@
 //! Process given file paths and calculate file verification code
 //! Internally it creates archive trees, which can be later traversed and fed to the FVC library, or can be used for debugging
 
-use super::{ExtractPolicy, Processor};
+use super::{ExtractPolicy, ProcessOptions, Processor, SymlinkMode, walk};
+use crate::audit::Decision;
 use crate::FVC2Hasher;
 use file_verification_code::FVCSha256Hasher;
 mod dag;
@@ -21,23 +22,39 @@ use file_verification_code::extract;
 use std::path::{Path, PathBuf};
 use std::fs::metadata;
 use log::*;
-use walkdir::WalkDir;
 use hex::ToHex;
-use file_verification_code::archive_tree::{Directory, Archive, File, Collection};
+use sha2::{Sha256, Digest};
+use file_verification_code::archive_tree::{Directory, Archive, File, Symlink, Collection};
+
+// archives nested this deep are hashed as files instead of being extracted, bounding recursion even without cycle detection
+const MAX_ARCHIVE_DEPTH: usize = 32;
 
 pub struct ExtractionProcessor {
     extract_policy: ExtractPolicy,
+    options: ProcessOptions,
 }
 
 impl Processor for ExtractionProcessor {
-    fn new(extract_policy: ExtractPolicy) -> Self {
-        Self { extract_policy: extract_policy }
+    fn new(extract_policy: ExtractPolicy, options: ProcessOptions) -> Self {
+        Self { extract_policy: extract_policy, options }
     }
 
     fn calculate_fvc(self: &Self, hasher: &mut FVC2Hasher, files: &[PathBuf]) -> std::io::Result<()> {
+        let collections = self.collect(files)?;
+        self.hash_collections(hasher, &collections);
+    
+        Ok(())
+    }
+}
+
+impl ExtractionProcessor {
+    /// collect processes the given files like calculate_fvc, but returns the tree of every file and archive found instead of hashing them
+    /// All given files share one ArchiveGraph, so an archive that contains another input's archive, which in turn contains it, is detected as a cycle too
+    pub fn collect(&self, files: &[PathBuf]) -> std::io::Result<Vec<Collection>> {
         let mut collections: Vec<Collection> = Vec::new();
+        let mut graph = dag::ArchiveGraph::new();
         for path in files {
-            match self.calculate_fvc_of(&mut dag::ArchiveGraph::new(), None, path) {
+            match self.calculate_fvc_of(&mut graph, None, 0, path) {
                 Ok(collection) => collections.push(collection),
                 Err(err) => return Err(err)
             }
@@ -47,34 +64,51 @@ impl Processor for ExtractionProcessor {
             debug!("collections: {}", serde_json::to_string(&collections)?);
         }
 
+        Ok(collections)
+    }
+
+    /// hash_collections feeds every file in the given trees to the FVC2Hasher
+    /// With hash_archive_file, archives are fed as their own sha256 instead of as their files
+    pub fn hash_collections(&self, hasher: &mut FVC2Hasher, collections: &[Collection]) {
         for collection in collections {
-            ExtractionProcessor::hash_collection(hasher, collection);
+            self.hash_collection(hasher, collection);
         }
-    
-        Ok(())
     }
-}
 
-impl ExtractionProcessor {
     // extract_or_process_file looks at a path and applies the given extraction policy
     // On the extremes ExtractPolicy::None and ExtractPolicy::All will always or never process a path as an archive
     // ExtractPolicy::Extension will look at the file extension and extract it if it looks like an archive, otherwise it will process it as a file
     // The ArchiveGraph can skip looking at the path since it is already known to be an archive
     // In every case, if an archive fails to extract, due to an extraction-specific error, it is treated as a file
     // If a general IO error is encountered at any point, that is immediately returned
-    fn extract_or_process_file<P: AsRef<Path>>(self: &Self, graph: &mut ArchiveGraph, current: Option<[u8; 32]>, file_path: P) -> std::io::Result<Collection> {
-        match self.extract_policy {
-            ExtractPolicy::None => match File::new(&file_path, None, None) { // nothing is to be extracted, immediately process as file
-                Ok(file) => Ok(Collection::File(file)),
+    // depth is the number of archives file_path is nested in, and files nested MAX_ARCHIVE_DEPTH deep are never extracted
+    // known is the sha256 and size of file_path when they were already calculated, so the file is not read again to hash it
+    fn extract_or_process_file<P: AsRef<Path>>(self: &Self, graph: &mut ArchiveGraph, current: Option<[u8; 32]>, depth: usize, file_path: P, known: Option<([u8; 32], u64)>) -> std::io::Result<Collection> {
+        self.options.check_cancelled()?;
+        let (extract_policy, reason) = match depth < MAX_ARCHIVE_DEPTH {
+            true => (self.extract_policy, "extraction is disabled"),
+            false => {
+                debug!("not extracting {}, it is already nested {} archives deep", file_path.as_ref().display(), depth);
+                (ExtractPolicy::None, "nested too many archives deep")
+            }
+        };
+
+        match extract_policy {
+            ExtractPolicy::None => match self.new_file(file_path.as_ref(), known) { // nothing is to be extracted, immediately process as file
+                Ok(file) => {
+                    self.options.audit(file_path.as_ref(), Decision::Hashed, reason);
+                    Ok(Collection::File(file))
+                },
                 Err(err) => Err(err)
             },
             ExtractPolicy::All | ExtractPolicy::Extension => {
                 // calculate sha256 to check if file is an already known archive
-                let sha256 = match get_sha256(&file_path) {
-                    Ok(sha256) => sha256,
-                    Err(err) => return Err(err)
+                let sha256 = match known {
+                    Some((sha256, _)) => sha256,
+                    None => get_sha256(&file_path)?
                 };
-                let known_archive = ArchiveGraph::contains(graph, sha256);
+                // without cycle detection no archive is ever known, and only the depth limit stops a quine
+                let known_archive = !self.options.no_cycle_detection && ArchiveGraph::contains(graph, sha256);
 
                 // if is an already known_archive, we might have a cycle
                 match (known_archive, current) {
@@ -82,7 +116,16 @@ impl ExtractionProcessor {
                         // check for cycle
                         match graph.add_edge(current, sha256) {
                             EdgeResult::Ok => (),
-                            EdgeResult::CycleDetected => return Ok(Collection::Empty), // exit early to avoid cycle
+                            EdgeResult::CycleDetected => { // exit early to avoid cycle
+                                self.options.cycles_detected.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                let cycle = format!("{} contains itself (sha256 {})", file_path.as_ref().display(), sha256.encode_hex::<String>());
+                                if self.options.fail_on_cycle {
+                                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, cycle));
+                                }
+                                warn!("{}, skipping it", cycle);
+                                self.options.audit(file_path.as_ref(), Decision::Skipped, "archive contains itself");
+                                return Ok(Collection::Empty);
+                            },
                             EdgeResult::KeyMissing(key) => panic!("key missing for known archive? {}", key.encode_hex::<String>())
                         };
 
@@ -91,29 +134,21 @@ impl ExtractionProcessor {
                             Err(err) => return Err(err)
                         };
                         // extract and process directory
-                        match open_archive(&file_path) {
+                        match open_archive(&file_path, &self.options) {
                             Ok(extracted_directory) => {
-                                match self.calculate_fvc_of(graph, Some(sha256), extracted_directory.path()) {
+                                self.options.audit(file_path.as_ref(), Decision::Extracted, "already extracted as an archive");
+                                match self.calculate_fvc_of(graph, Some(sha256), depth + 1, extracted_directory.path()) {
                                     Ok(collection) => {
-                                        match collection {
-                                            Collection::File(file) => {
-                                                archive.files.insert(file_path.as_ref().to_path_buf(), file);
-                                            },
-                                            Collection::Archive(archve) => {
-                                                archive.archives.insert(file_path.as_ref().to_path_buf(), archve);
-                                            },
-                                            Collection::Directory(directory) => {
-                                                archive.files = directory.files;
-                                                archive.archives = directory.archives;
-                                            },
-                                            Collection::Empty => (),
-                                        };
+                                        self.add_extracted(&mut archive, file_path.as_ref(), extracted_directory.path(), collection);
                                         match extracted_directory.close() { // clean up extraction
                                             Ok(()) => return Ok(Collection::Archive(archive)),
                                             Err(err) => return Err(err)
                                         };
                                     },
-                                    Err(err) => return Err(err)
+                                    Err(err) => {
+                                        discard(extracted_directory);
+                                        return Err(err);
+                                    }
                                 }
                             },
                             Err(err) => match err {
@@ -130,29 +165,21 @@ impl ExtractionProcessor {
                             Err(err) => return Err(err)
                         };
                         // extract and process directory
-                        match open_archive(&file_path) {
+                        match open_archive(&file_path, &self.options) {
                             Ok(extracted_directory) => {
-                                match self.calculate_fvc_of(graph, Some(sha256), extracted_directory.path()) {
+                                self.options.audit(file_path.as_ref(), Decision::Extracted, "already extracted as an archive");
+                                match self.calculate_fvc_of(graph, Some(sha256), depth + 1, extracted_directory.path()) {
                                     Ok(collection) => {
-                                        match collection {
-                                            Collection::File(file) => {
-                                                archive.files.insert(file_path.as_ref().to_path_buf(), file);
-                                            },
-                                            Collection::Archive(archve) => {
-                                                archive.archives.insert(file_path.as_ref().to_owned(), archve);
-                                            }
-                                            Collection::Directory(directory) => {
-                                                archive.files = directory.files;
-                                                archive.archives = directory.archives;
-                                            },
-                                            Collection::Empty => ()
-                                        };
+                                        self.add_extracted(&mut archive, file_path.as_ref(), extracted_directory.path(), collection);
                                         match extracted_directory.close() { // clean up extraction
                                             Ok(()) => return Ok(Collection::Archive(archive)),
                                             Err(err) => return Err(err)
                                         };
                                     },
-                                    Err(err) => return Err(err)
+                                    Err(err) => {
+                                        discard(extracted_directory);
+                                        return Err(err);
+                                    }
                                 }
                             },
                             Err(err) => match err {
@@ -166,111 +193,164 @@ impl ExtractionProcessor {
 
                 // unknown if archive or file
                 // return early if archive was extracted and processed, otherwise fall to file process below
-                match (self.extract_policy, extract::is_extractable(&file_path)) {
-                    (ExtractPolicy::Extension, 0) => (),
+                let reason = match (self.extract_policy, extract::is_extractable(&file_path)) {
+                    (ExtractPolicy::Extension, 0) => "not an archive by its extension".to_string(),
+                    // an empty file holds no archive whatever its extension, so extracting it would only fail
+                    _ if metadata(file_path.as_ref())?.len() == 0 => "empty file".to_string(),
                     (_, 100) => {
                         let mut archive = match Archive::new(&file_path, None, Some(sha256)) {
                             Ok(archive) => archive,
                             Err(err) => return Err(err)
                         };
-                        match open_archive(&file_path) {
+                        match open_archive(&file_path, &self.options) {
                             Err(err) => match err {
                                 compress_tools::Error::Io(err) => return Err(err),
-                                _ => debug!("error extracting 100 confidence archive: {}", file_path.as_ref().display())
+                                err => {
+                                    debug!("error extracting 100 confidence archive: {}", file_path.as_ref().display());
+                                    self.check_stream(file_path.as_ref())?;
+                                    format!("failed to extract: {}", err)
+                                }
+                            },
+                            Ok(extracted_directory) if self.exceeds_archive_size(file_path.as_ref(), extracted_directory.path()) => {
+                                discard(extracted_directory);
+                                "extracts to more than the maximum archive size".to_string()
                             },
                             Ok(extracted_directory) => {
-                                graph.insert(sha256);
-                                match self.calculate_fvc_of(graph, Some(sha256), extracted_directory.path()) {
+                                self.options.audit(file_path.as_ref(), Decision::Extracted, "archive extension");
+                                if !self.options.no_cycle_detection {
+                                    graph.insert(sha256);
+                                }
+                                match self.calculate_fvc_of(graph, Some(sha256), depth + 1, extracted_directory.path()) {
                                     Ok(collection) => {
-                                        match collection {
-                                            Collection::File(file) => {
-                                                archive.files.insert(file_path.as_ref().to_path_buf(), file);
-                                            },
-                                            Collection::Archive(archve) => {
-                                                archive.archives.insert(file_path.as_ref().to_owned(), archve);
-                                            },
-                                            Collection::Directory(directory) => {
-                                                archive.files = directory.files;
-                                                archive.archives = directory.archives;
-                                            },
-                                            Collection::Empty => ()
-                                        };
+                                        self.add_extracted(&mut archive, file_path.as_ref(), extracted_directory.path(), collection);
                                         match extracted_directory.close() { // clean up extraction
                                             Ok(()) => return Ok(Collection::Archive(archive)),
                                             Err(err) => return Err(err)
                                         };
                                     },
-                                    Err(err) => return Err(err)
+                                    Err(err) => {
+                                        discard(extracted_directory);
+                                        return Err(err);
+                                    }
                                 }
                             }
                         }
                     },
                     (_, _confidence) => {
                         // for now, we try to extract anything over 0, so this arm is the same as ExtractPolicy::All
-                        match open_archive(&file_path) {
+                        match open_archive(&file_path, &self.options) {
                             Err(err) => match err {
                                 compress_tools::Error::Io(err) => return Err(err),
-                                _ => ()
+                                err => {
+                                    self.check_stream(file_path.as_ref())?;
+                                    format!("failed to extract: {}", err)
+                                }
+                            },
+                            Ok(extracted_directory) if self.exceeds_archive_size(file_path.as_ref(), extracted_directory.path()) => {
+                                discard(extracted_directory);
+                                "extracts to more than the maximum archive size".to_string()
                             },
                             Ok(extracted_directory) => {
-                                graph.insert(sha256);
+                                let why = match self.extract_policy {
+                                    ExtractPolicy::All => "every file is tried as an archive",
+                                    _ => "possibly an archive"
+                                };
+                                self.options.audit(file_path.as_ref(), Decision::Extracted, why);
+                                if !self.options.no_cycle_detection {
+                                    graph.insert(sha256);
+                                }
                                 let mut archive = match Archive::new(&file_path, None, Some(sha256)) {
                                     Ok(archive) => archive,
                                     Err(err) => return Err(err)
                                 };
-                                match self.calculate_fvc_of(graph, Some(sha256), extracted_directory.path()) {
+                                match self.calculate_fvc_of(graph, Some(sha256), depth + 1, extracted_directory.path()) {
                                     Ok(collection) => {
-                                        match collection {
-                                            Collection::File(file) => {
-                                                archive.files.insert(file_path.as_ref().to_path_buf(), file);
-                                            },
-                                            Collection::Archive(archve) => {
-                                                archive.archives.insert(file_path.as_ref().to_path_buf(), archve);
-                                            },
-                                            Collection::Directory(directory) => {
-                                                archive.files = directory.files;
-                                                archive.archives = directory.archives;
-                                            },
-                                            Collection::Empty => ()
-                                        };
+                                        self.add_extracted(&mut archive, file_path.as_ref(), extracted_directory.path(), collection);
                                         match extracted_directory.close() { // clean up extraction
                                             Ok(()) => return Ok(Collection::Archive(archive)),
                                             Err(err) => return Err(err)
                                         };
                                     },
-                                    Err(err) => return Err(err)
+                                    Err(err) => {
+                                        discard(extracted_directory);
+                                        return Err(err);
+                                    }
                                 }
                             }
                         }
                     }
-                }
+                };
 
                 // was not able to, or decided not to, process as an archive
-                match File::new(&file_path, None, None) {
-                    Ok(file) => Ok(Collection::File(file)),
+                match self.new_file(file_path.as_ref(), known) {
+                    Ok(file) => {
+                        self.options.audit(file_path.as_ref(), Decision::Hashed, &reason);
+                        Ok(Collection::File(file))
+                    },
                     Err(err) => Err(err)
                 }
             }
         }
     }
 
+    // new_file records the file at path, with its whitespace normalized if asked to, and otherwise with its known sha256 and size if given
+    fn new_file(&self, path: &Path, known: Option<([u8; 32], u64)>) -> std::io::Result<File> {
+        match (self.options.normalized_sha256(path)?, known) {
+            (Some(normalized), _) => File::new(path, None, Some(normalized)),
+            (None, Some((sha256, size))) => File::new(path, Some(size), Some(sha256)),
+            (None, None) => File::new(path, None, None)
+        }
+    }
+
+    // prehash calculates the sha256 and size of every given file on all cores, when asked to, for extract_or_process_file to pick up
+    // A file that fails to hash is left unknown, to fail again, and be reported, when it is processed
+    #[cfg(feature = "parallel")]
+    fn prehash(&self, files: &[PathBuf]) -> Vec<Option<([u8; 32], u64)>> {
+        use rayon::prelude::*;
+
+        match self.options.parallel {
+            true => files.par_iter().map(|path| std::fs::File::open(path).and_then(file_verification_code::digest::sha256_of).ok()).collect(),
+            false => vec![None; files.len()]
+        }
+    }
+
+    // without the parallel feature files are hashed as they are processed
+    #[cfg(not(feature = "parallel"))]
+    fn prehash(&self, files: &[PathBuf]) -> Vec<Option<([u8; 32], u64)>> {
+        vec![None; files.len()]
+    }
+
     // calculate_fvc_of acts like calculate_fvc, buts adds the ArchiveGraph and current archive to protect against quines
     // the archive graph is a directed acyclic graph, and if a cycle is ever detected, that edge is not added, and thus that archive is not processed futher
-    fn calculate_fvc_of(self: &Self, graph: &mut ArchiveGraph, current: Option<[u8; 32]>, filepath: &Path) -> std::io::Result<Collection> {
+    fn calculate_fvc_of(self: &Self, graph: &mut ArchiveGraph, current: Option<[u8; 32]>, depth: usize, filepath: &Path) -> std::io::Result<Collection> {
+        self.options.check_cancelled()?;
         let stat = match metadata(filepath) {
             Ok(metadata) => metadata,
             Err(err) => {
-                return Err(err);
+                return Err(self.options.audit_err(filepath, err));
             }
         };
 
         if stat.is_file() {
-            return self.extract_or_process_file(graph, current, filepath);            
+            let collection = self.extract_or_process_file(graph, current, depth, filepath, None).map_err(|err| self.options.audit_err(filepath, err))?;
+            return Ok(self.flatten(self.report(filepath, self.exclude_by_hash(filepath, collection))));
         } else if stat.is_dir() {
             info!("Adding directory \"{}\"", filepath.display());
             let mut directory = Directory::new(filepath);
+            let mut files = Vec::new();
 
-            for entry in WalkDir::new(filepath) {
+            // archive junk is only looked for in extracted archives, current being the archive filepath was extracted from
+            let excluded = |entry: &walkdir::DirEntry| {
+                let reason = match self.options.is_excluded(entry) {
+                    true => "excluded",
+                    false if current.is_some() && self.options.is_archive_junk(entry) => "archive junk",
+                    false => return false
+                };
+                self.options.audit(entry.path(), Decision::Skipped, reason);
+                true
+            };
+            // links are never followed, so a link in an extracted archive cannot pull in files from outside of it, such as the host's /etc/passwd
+            for entry in walk(filepath).follow_links(false).into_iter().filter_entry(|entry| !excluded(entry)) {
                 let dir_entry = match entry {
                     Ok(dir_entry) => dir_entry,
                     Err(err) => {
@@ -280,86 +360,271 @@ impl ExtractionProcessor {
                 };
                 trace!("at entry {}", dir_entry.path().display());
 
-                // only process files
-                if dir_entry.file_type().is_file() {
-                    trace!("trying file {}", dir_entry.path().display());
-                    match self.extract_or_process_file(graph, current, dir_entry.path()) {
-                        Ok(collection) => match collection {
-                            Collection::Directory(_) => panic!("WalkDir should be ignoring directories and returning files directly"),
-                            Collection::File(file) => {
-                                directory.files.insert(dir_entry.path().to_owned(), file);
-                            },
-                            Collection::Archive(archive) => {
-                                directory.archives.insert(dir_entry.path().to_owned(), archive);
-                            },
-                            Collection::Empty => ()
+                // refuse crowded directories before walking into them
+                if dir_entry.file_type().is_dir() {
+                    self.options.check_dir_entries(dir_entry.path()).map_err(|err| self.options.audit_err(dir_entry.path(), err))?;
+                }
+
+                // a followed link is processed as the file it leads to, under the link's own path
+                if dir_entry.file_type().is_symlink() && self.follows(dir_entry.path(), filepath) {
+                    files.push(dir_entry.into_path());
+                    continue;
+                }
+
+                // record symlinks without following them, relative to the directory being walked
+                if dir_entry.file_type().is_symlink() {
+                    match Symlink::new(dir_entry.path(), filepath) {
+                        Ok(symlink) => {
+                            directory.symlinks.insert(dir_entry.path().to_owned(), symlink);
                         },
                         Err(err) => {
-                            log::error!("error processing file {}", dir_entry.path().display());
+                            log::error!("error reading symlink {}", dir_entry.path().display());
                             return Err(err);
                         }
                     }
                 }
+
+                // files are processed once the walk is done, so they can be hashed together first
+                if dir_entry.file_type().is_file() {
+                    files.push(dir_entry.into_path());
+                }
+            }
+
+            let known = self.prehash(&files);
+            for (file_path, known) in files.into_iter().zip(known) {
+                trace!("trying file {}", file_path.display());
+                match self.extract_or_process_file(graph, current, depth, &file_path, known) {
+                    Ok(collection) => match self.flatten(self.report(&file_path, self.exclude_by_hash(&file_path, collection))) {
+                        Collection::Directory(_) => panic!("WalkDir should be ignoring directories and returning files directly"),
+                        Collection::File(file) => {
+                            directory.files.insert(file_path, file);
+                        },
+                        Collection::Archive(archive) => {
+                            directory.archives.insert(file_path, archive);
+                        },
+                        Collection::Empty => ()
+                    },
+                    Err(err) => {
+                        log::error!("error processing file {}", file_path.display());
+                        return Err(self.options.audit_err(&file_path, err));
+                    }
+                }
             }
 
             return Ok(Collection::Directory(directory));
         } else {
             info!("Skipping irregular file {}", filepath.display());
+            self.options.audit(filepath, Decision::Skipped, "not a regular file");
         }
     
         Ok(Collection::Empty)
     }
 
+    // follows returns whether the link at link_path is to be processed as the file it leads to, which with SymlinkMode::Follow it is
+    // if it leads to a file inside root. A link chain that loops, or leads outside of root, such as out of an extracted archive, is never followed
+    fn follows(&self, link_path: &Path, root: &Path) -> bool {
+        if self.options.symlinks != SymlinkMode::Follow {
+            return false;
+        }
+
+        let resolved = match std::fs::canonicalize(link_path) {
+            Ok(resolved) => resolved,
+            Err(err) => {
+                warn!("not following {}, it cannot be resolved: {}", link_path.display(), err);
+                return false;
+            }
+        };
+        match std::fs::canonicalize(root) {
+            Ok(root) if resolved.starts_with(&root) => resolved.is_file(),
+            _ => {
+                warn!("not following {}, it leads outside of {}", link_path.display(), root.display());
+                false
+            }
+        }
+    }
+
+    // add_extracted adds the collection found by processing an extracted archive to that archive
+    // Their paths are recorded relative to the extraction root, so the tree does not depend on where the archive was temporarily extracted to
+    fn add_extracted(&self, archive: &mut Archive, archive_path: &Path, extraction_root: &Path, collection: Collection) {
+        let relative = |path: PathBuf| match path.strip_prefix(extraction_root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path
+        };
+
+        match collection {
+            Collection::File(file) => {
+                archive.files.insert(archive_path.to_path_buf(), file);
+            },
+            Collection::Archive(nested) => {
+                archive.archives.insert(archive_path.to_path_buf(), nested);
+            },
+            // extractions are walked recursively, so files and archives found in any subdirectory of the extraction arrive in the Directory and are kept as-is
+            Collection::Directory(directory) => {
+                archive.files.extend(directory.files.into_iter().map(|(path, file)| (relative(path), file)));
+                archive.archives.extend(directory.archives.into_iter().map(|(path, nested)| (relative(path), nested)));
+                archive.symlinks.extend(directory.symlinks.into_iter().map(|(path, symlink)| (relative(path), symlink)));
+            },
+            Collection::Empty => ()
+        };
+
+        // the extracted files no longer show the order they were stored in, so ask libarchive for it
+        if self.options.entry_order {
+            match extract::list_entries(archive_path) {
+                Ok(entries) => archive.entry_order = entries,
+                Err(err) => warn!("error listing entries of {}: {}", archive_path.display(), err)
+            }
+        }
+
+        // the comment is recorded before the metadata hash it is folded into
+        match extract::zip_comment(archive_path) {
+            Ok(comment) => archive.comment = comment,
+            Err(err) => warn!("error reading comment of {}: {}", archive_path.display(), err)
+        }
+
+        if self.options.uncompressed_size {
+            match extract::uncompressed_size(archive_path) {
+                Ok(size) => archive.record_uncompressed_size(size),
+                Err(err) => warn!("error decompressing {}: {}", archive_path.display(), err)
+            }
+        }
+    }
+
+    // exceeds_archive_size returns whether the files extracted from an archive add up to more than max_archive_size, in which case it is hashed as a file instead
+    fn exceeds_archive_size(&self, archive_path: &Path, extraction_root: &Path) -> bool {
+        let max_archive_size = match self.options.max_archive_size {
+            Some(max_archive_size) => max_archive_size,
+            None => return false
+        };
+
+        let size: u64 = walk(extraction_root).into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        if size > max_archive_size {
+            warn!("{} extracts to {} bytes, more than the maximum of {}, hashing it as a file", archive_path.display(), size, max_archive_size);
+            return true;
+        }
+
+        false
+    }
+
+    // check_stream looks at a file that failed to extract, to tell one that is not an archive from a truncated or corrupt compressed stream
+    // Corrupt streams are an error with strict_extract, otherwise they are warned about and hashed as files like anything else that failed to extract
+    fn check_stream(&self, path: &Path) -> std::io::Result<()> {
+        let compression = match extract::compressed_stream(path)? {
+            Some(compression) => compression,
+            None => return Ok(())
+        };
+        if let Err(err) = extract::uncompressed_size(path) {
+            let message = format!("{} is a truncated or corrupt {} stream: {}", path.display(), compression, err);
+            if self.options.strict_extract {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+            }
+            warn!("{}, hashing it as a file", message);
+        }
+
+        Ok(())
+    }
+
+    // exclude_by_hash drops a file whose sha256 is excluded, and passes anything else on
+    fn exclude_by_hash(&self, path: &Path, collection: Collection) -> Collection {
+        match collection {
+            Collection::File(file) if self.options.is_excluded_hash(&file.sha256) => {
+                self.options.audit(path, Decision::Skipped, "excluded by hash");
+                Collection::Empty
+            },
+            collection => collection
+        }
+    }
+
+    // report emits an event for the file or archive processed at path, and passes the collection on
+    fn report(&self, path: &Path, collection: Collection) -> Collection {
+        match &collection {
+            Collection::File(file) => self.options.on_file(path, &file.sha256, file.size),
+            Collection::Archive(archive) => self.options.on_archive(path, &archive.sha256),
+            Collection::Directory(_) | Collection::Empty => ()
+        };
+        collection
+    }
+
+    // flatten replaces an archive holding a single file by that file, when asked to
+    fn flatten(&self, collection: Collection) -> Collection {
+        match collection {
+            Collection::Archive(archive) if self.options.flatten_single => archive.flatten_single(),
+            collection => collection
+        }
+    }
+
     // hash_collection process the given collection and feeds its files to the FVC2Hasher
-    fn hash_collection(hasher: &mut FVC2Hasher, collection: Collection) {
+    fn hash_collection(&self, hasher: &mut FVC2Hasher, collection: &Collection) {
         match collection {
             Collection::Empty => (),
             Collection::File(file) => hasher.read_sha256(file.sha256),
-            Collection::Archive(archive) => {
-                for (_path, file) in archive.files {
-                    hasher.read_sha256(file.sha256);
-                }
-                for (_path, archive) in archive.archives {
-                    ExtractionProcessor::hash_collection(hasher, Collection::Archive(archive))
-                }
-            },
+            Collection::Archive(archive) => self.hash_archive(hasher, archive),
             Collection::Directory(directory) => {
-                for (_path, file) in directory.files {
+                for file in directory.files.values() {
                     hasher.read_sha256(file.sha256);
                 }
-                for (_path, archive) in directory.archives {
-                    ExtractionProcessor::hash_collection(hasher, Collection::Archive(archive))
+                for archive in directory.archives.values() {
+                    self.hash_archive(hasher, archive)
                 }                
+                self.hash_symlinks(hasher, directory.symlinks.values());
             },
         }
     }
+
+    // hash_archive feeds the files of the given archive, and of its nested archives, to the FVC2Hasher
+    // With hash_archive_file the archive's own sha256 stands for all of them, nested archives included
+    fn hash_archive(&self, hasher: &mut FVC2Hasher, archive: &Archive) {
+        if self.options.hash_archive_file {
+            hasher.read_sha256(archive.sha256);
+            return;
+        }
+
+        for file in archive.files.values() {
+            hasher.read_sha256(file.sha256);
+        }
+        for archive in archive.archives.values() {
+            self.hash_archive(hasher, archive)
+        }
+        self.hash_symlinks(hasher, archive.symlinks.values());
+    }
+
+    // hash_symlinks feeds the sha256 of the recorded target of every symlink to the FVC2Hasher, with SymlinkMode::HashTarget
+    // Followed links were recorded as files, and in the other modes links contribute nothing
+    fn hash_symlinks<'a>(&self, hasher: &mut FVC2Hasher, symlinks: impl Iterator<Item = &'a Symlink>) {
+        if self.options.symlinks != SymlinkMode::HashTarget {
+            return;
+        }
+
+        for symlink in symlinks {
+            hasher.read_sha256(Sha256::digest(symlink.target.to_string_lossy().as_bytes()).into());
+        }
+    }
 }
 
 // get_sha256 calculates and returns an array of bytes represeting the sha256 of the given file
+// The file is read in fixed-size chunks, so hashing a huge archive or member takes no more memory than a small one
 fn get_sha256<P: AsRef<Path>>(path: P) -> std::io::Result<[u8; 32]> {
-    use sha2::{Sha256, Digest};
-    use std::io::Read;
-
-    let mut hasher = Sha256::new();
-    let mut file = match std::fs::File::open(path) {
-        Ok(file) => file,
-        Err(err) => return Err(err)
-    };
-    let mut buf = Vec::new();
-    let sha256: [u8; 32] = match file.read_to_end(&mut buf) {
-        Ok(_size) => {
-            hasher.update(buf);
-            hasher.finalize().into()
-        },
-        Err(err) => return Err(err)
-    };
+    let (sha256, _size) = file_verification_code::digest::sha256_of(std::fs::File::open(path)?)?;
 
     Ok(sha256)
 }
 
+// discard cleans up the extraction of an archive whose processing failed or was cancelled
+// Failing to clean up is logged rather than returned, so the error that stopped processing is the one reported
+fn discard(extracted_directory: tempdir::TempDir) {
+    let path = extracted_directory.path().to_path_buf();
+    if let Err(err) = extracted_directory.close() {
+        warn!("error cleaning up extracted archive {}: {}", path.display(), err);
+    }
+}
+
 // open archive creates a temporary directory and extracts the given archive to it
 // in the case of an extraction error, the temporary directory is cleaned-up here, otherwise it needs to be cleaned up by the receiever
-fn open_archive<P: AsRef<Path>>(archive_path: P) -> compress_tools::Result<tempdir::TempDir> {
+fn open_archive<P: AsRef<Path>>(archive_path: P, options: &ProcessOptions) -> compress_tools::Result<tempdir::TempDir> {
     let tmp_prefix = match archive_path.as_ref().file_name() {
         Some(file_name) => format!("fvc_extracted_archive.{:?}", file_name),
         None => format!("fvc_extracted_archive.{:?}", archive_path.as_ref())
@@ -369,7 +634,20 @@ fn open_archive<P: AsRef<Path>>(archive_path: P) -> compress_tools::Result<tempd
         Err(err) => return Err(compress_tools::Error::Io(err))
     };
 
-    match extract::extract_archive(&archive_path, tmp.as_ref()) {
+    let package = options.package_payload && archive_path.as_ref().extension() == Some(std::ffi::OsStr::new("deb"));
+    #[cfg(feature = "zstd")]
+    let extracted = match &options.zstd_dictionary {
+        _ if package => extract::extract_package_payload(&archive_path, tmp.as_ref()),
+        Some(dictionary) => extract::extract_archive_with_dictionary(&archive_path, tmp.as_ref(), dictionary),
+        None => extract::extract_archive(&archive_path, tmp.as_ref())
+    };
+    #[cfg(not(feature = "zstd"))]
+    let extracted = match package {
+        true => extract::extract_package_payload(&archive_path, tmp.as_ref()),
+        false => extract::extract_archive(&archive_path, tmp.as_ref())
+    };
+
+    match extracted {
         Ok(()) => {
             info!("extracted archive {}", archive_path.as_ref().display());
             Ok(tmp)
@@ -382,4 +660,517 @@ fn open_archive<P: AsRef<Path>>(archive_path: P) -> compress_tools::Result<tempd
             Err(err)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use file_verification_code::FVCHasher;
+
+    #[test]
+    fn archive_in_subdirectory_of_archive() {
+        // nested_archive.zip holds sub/inner.zip, which holds the flat files
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let collection = processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, Path::new("test_data/nested_archive.zip")).expect("processing nested archive");
+        let outer = match collection {
+            Collection::Archive(archive) => archive,
+            other => panic!("expected an archive, found {:?}", other)
+        };
+        assert!(outer.files.is_empty());
+        assert_eq!(outer.archives.len(), 1);
+        let (inner_path, inner) = outer.archives.iter().next().unwrap();
+        assert_eq!(inner_path, Path::new("sub/inner.zip"));
+        assert_eq!(inner.files.len(), 3);
+
+        let mut hasher = FVC2Hasher::new();
+        processor.calculate_fvc(&mut hasher, &[PathBuf::from("test_data/nested_archive.zip")]).expect("processing nested archive");
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+
+    #[test]
+    fn tree_is_canonical() {
+        // extraction goes to fresh temporary directories each time, which must not show up in the tree
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let files = [PathBuf::from("test_data/archive_hierarchy.tar.gz"), PathBuf::from("test_data/flat_files")];
+        let first = serde_json::to_string(&processor.collect(&files).expect("processing files")).expect("serializing tree");
+        let second = serde_json::to_string(&processor.collect(&files).expect("processing files")).expect("serializing tree");
+        assert_eq!(first, second);
+        assert!(!first.contains("fvc_extracted_archive"));
+    }
+
+    #[test]
+    fn entry_order_is_archive_order() {
+        let options = ProcessOptions { entry_order: true, ..Default::default() };
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, options);
+        let archive = match processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, Path::new("test_data/reordered_archive.tar.gz")).expect("processing archive") {
+            Collection::Archive(archive) => archive,
+            other => panic!("expected an archive, found {:?}", other)
+        };
+        // stored out of name order, but with the same contents as the flat files
+        assert_eq!(archive.entry_order, ["zap.txt", "foo.txt", "bar.txt"].map(PathBuf::from));
+
+        // nothing is recorded unless asked for
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        match processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, Path::new("test_data/reordered_archive.tar.gz")).expect("processing archive") {
+            Collection::Archive(archive) => assert!(archive.entry_order.is_empty()),
+            other => panic!("expected an archive, found {:?}", other)
+        };
+    }
+
+    #[test]
+    fn initramfs_is_extracted() {
+        // a gzipped cpio archive of the flat files, under its usual name and as a bare initramfs image
+        let tmp = tempdir::TempDir::new("fvc_initramfs").expect("creating temporary directory");
+        let image = tmp.path().join("initramfs.img");
+        std::fs::copy("test_data/flat_files.cpio.gz", &image).expect("copying image");
+
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        for path in [PathBuf::from("test_data/flat_files.cpio.gz"), image] {
+            let mut hasher = FVC2Hasher::new();
+            processor.calculate_fvc(&mut hasher, std::slice::from_ref(&path)).expect("processing initramfs");
+            assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim(), "{}", path.display());
+        }
+
+        // a .img that is not an initramfs, such as a disk image, is hashed as a file
+        let disk = tmp.path().join("disk.img");
+        std::fs::write(&disk, [0u8; 512]).expect("writing disk image");
+        match processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, &disk).expect("processing disk image") {
+            Collection::File(_) => (),
+            other => panic!("expected a file, found {:?}", other)
+        };
+    }
+
+    #[test]
+    fn rar_archive_is_extracted() {
+        // a RAR archive storing the flat files uncompressed
+        let path = PathBuf::from("test_data/flat_archive.rar");
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        assert!(matches!(processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, &path).expect("processing rar"), Collection::Archive(_)));
+
+        let mut hasher = FVC2Hasher::new();
+        processor.calculate_fvc(&mut hasher, &[path]).expect("processing rar");
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+
+    #[test]
+    fn streamed_zip_with_data_descriptors_is_extracted() {
+        // streamed_archive.zip was written to a pipe, so every local header has bit 3 set and zero sizes, with the sizes in a data descriptor after the data
+        // foo.txt is stored, which leaves no compressed stream to find its end, and bar.txt and zap.txt are deflated
+        let path = PathBuf::from("test_data/streamed_archive.zip");
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let archive = match processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, &path).expect("processing streamed zip") {
+            Collection::Archive(archive) => archive,
+            other => panic!("expected an archive, found {:?}", other)
+        };
+        for name in ["foo.txt", "bar.txt", "zap.txt"] {
+            let file = archive.files.get(Path::new(name)).unwrap_or_else(|| panic!("{} was not extracted", name));
+            assert_eq!(file.sha256, get_sha256(Path::new("test_data/flat_files").join(name)).unwrap(), "{}", name);
+        }
+
+        let mut hasher = FVC2Hasher::new();
+        processor.calculate_fvc(&mut hasher, &[path]).expect("processing streamed zip");
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_equals_sequential() {
+        let process = |parallel: bool| {
+            let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions { parallel, ..Default::default() });
+            // extracted archives are walked like the directory, so their files are hashed in parallel too
+            let inputs = ["test_data/flat_files", "test_data/archive_hierarchy.tar.gz", "test_data/nested_archive.zip"].map(PathBuf::from);
+            let collections = processor.collect(&inputs).expect("processing test data");
+            let mut hasher = FVC2Hasher::new();
+            processor.hash_collections(&mut hasher, &collections);
+            (hasher.hex(), serde_json::to_string(&collections).unwrap())
+        };
+
+        assert_eq!(process(true), process(false));
+    }
+
+    #[test]
+    fn double_compressed_archive_is_fully_decompressed() {
+        // a gzip of flat_archive.tar.gz, libarchive peels both gzip filters before detecting the tar inside
+        let path = PathBuf::from("test_data/flat_archive.tar.gz.gz");
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let archive = match processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, &path).expect("processing double-compressed archive") {
+            Collection::Archive(archive) => archive,
+            other => panic!("expected an archive, found {:?}", other)
+        };
+        assert!(archive.archives.is_empty(), "inner stream was kept as an archive: {:?}", archive);
+
+        let mut hasher = FVC2Hasher::new();
+        processor.calculate_fvc(&mut hasher, &[path]).expect("processing double-compressed archive");
+        assert_eq!(hasher.hex(), std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap().trim());
+    }
+
+    #[test]
+    fn archive_junk_is_excluded() {
+        // the flat files zipped on macOS, with __MACOSX, .DS_Store and Thumbs.db entries
+        let path = PathBuf::from("test_data/macos_archive.zip");
+        let fvc = |options: ProcessOptions| {
+            let mut hasher = FVC2Hasher::new();
+            ExtractionProcessor::new(ExtractPolicy::Extension, options).calculate_fvc(&mut hasher, std::slice::from_ref(&path)).expect("processing archive");
+            hasher.hex()
+        };
+
+        let flat_files = std::fs::read_to_string("test_data/flat_files.fvc2.hex").unwrap();
+        assert_eq!(fvc(ProcessOptions { exclude_archive_junk: true, ..Default::default() }), flat_files.trim());
+        assert_ne!(fvc(ProcessOptions::default()), flat_files.trim());
+    }
+
+    #[test]
+    fn inputs_share_archive_graph() {
+        // the zip quine extracts to a copy of itself, so each of these inputs contains the other
+        let tmp = tempdir::TempDir::new("fvc_shared_graph").expect("creating temporary directory");
+        let copy = tmp.path().join("quine_copy.zip");
+        std::fs::copy("test_data/quines/quine.zip", &copy).expect("copying quine");
+
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let collections = processor.collect(&[PathBuf::from("test_data/quines/quine.zip"), copy]).expect("processing quines");
+        assert_eq!(collections.len(), 2);
+        for collection in collections.iter() {
+            match collection {
+                Collection::Archive(archive) => assert!(archive.archives.is_empty() && archive.files.is_empty(), "cycle was not broken: {:?}", archive),
+                other => panic!("expected an archive, found {:?}", other)
+            }
+        }
+
+        // an archive given twice is still extracted, and counted, twice
+        let archive = PathBuf::from("test_data/flat_archive.tar.gz");
+        let mut once = FVC2Hasher::new();
+        processor.calculate_fvc(&mut once, std::slice::from_ref(&archive)).expect("processing archive");
+        let mut twice = FVC2Hasher::new();
+        processor.calculate_fvc(&mut twice, &[archive.clone(), archive]).expect("processing archive twice");
+        let mut expected = FVC2Hasher::new();
+        expected.extend_from(&once);
+        expected.extend_from(&once);
+        assert_eq!(twice.hex(), expected.hex());
+    }
+
+    #[test]
+    fn cycle_is_reported() {
+        use std::sync::atomic::Ordering;
+
+        let quine = PathBuf::from("test_data/quines/quine.zip");
+        let options = ProcessOptions::default();
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, options.clone());
+        processor.calculate_fvc(&mut FVC2Hasher::new(), std::slice::from_ref(&quine)).expect("processing quine");
+        assert_eq!(options.cycles_detected.load(Ordering::Relaxed), 1);
+
+        let options = ProcessOptions { fail_on_cycle: true, ..Default::default() };
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, options.clone());
+        let err = processor.calculate_fvc(&mut FVC2Hasher::new(), &[quine]).expect_err("cycle should fail the run");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("contains itself"), "{}", err);
+        assert_eq!(options.cycles_detected.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_modes_contribute_differently() {
+        let tmp = tempdir::TempDir::new("fvc_symlinks").expect("creating temporary directory");
+        std::fs::copy("test_data/flat_files/foo.txt", tmp.path().join("foo.txt")).unwrap();
+        std::os::unix::fs::symlink("foo.txt", tmp.path().join("link")).unwrap();
+        // a loop is recorded, but never followed
+        std::os::unix::fs::symlink("loop_b", tmp.path().join("loop_a")).unwrap();
+        std::os::unix::fs::symlink("loop_a", tmp.path().join("loop_b")).unwrap();
+
+        let fvc = |symlinks: SymlinkMode| {
+            let mut hasher = FVC2Hasher::new();
+            ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions { symlinks, ..Default::default() }).calculate_fvc(&mut hasher, &[tmp.path().to_path_buf()]).expect("processing directory");
+            hasher.hex()
+        };
+        let foo = get_sha256("test_data/flat_files/foo.txt").unwrap();
+        let code = |sha256s: &[[u8; 32]]| {
+            let mut hasher = FVC2Hasher::new();
+            for sha256 in sha256s {
+                hasher.read_sha256(*sha256);
+            }
+            hasher.hex()
+        };
+
+        assert_eq!(fvc(SymlinkMode::Skip), code(&[foo]));
+        assert_eq!(fvc(SymlinkMode::Follow), code(&[foo, foo]));
+        let targets = ["foo.txt", "loop_a", "loop_b"].map(|target| Sha256::digest(target).into());
+        assert_eq!(fvc(SymlinkMode::HashTarget), code(&[foo, targets[0], targets[1], targets[2]]));
+    }
+
+    #[test]
+    fn symlink_out_of_archive_is_not_followed() {
+        // foo.txt, and passwd linking to /etc/passwd
+        let path = PathBuf::from("test_data/escaping_symlink.tar.gz");
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let archive = match processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, &path).expect("processing archive") {
+            Collection::Archive(archive) => archive,
+            other => panic!("expected an archive, found {:?}", other)
+        };
+
+        assert_eq!(archive.files.keys().collect::<Vec<_>>(), vec![Path::new("foo.txt")]);
+        let link = archive.symlinks.get(Path::new("passwd")).expect("recording symlink");
+        assert!(link.external);
+        assert_eq!(link.target, PathBuf::from("/etc/passwd"));
+
+        let mut hasher = FVC2Hasher::new();
+        processor.calculate_fvc(&mut hasher, &[path]).expect("processing archive");
+        let mut foo = FVC2Hasher::new();
+        foo.read_sha256(get_sha256("test_data/flat_files/foo.txt").unwrap());
+        assert_eq!(hasher.hex(), foo.hex());
+    }
+
+    #[test]
+    fn cancelled_extractions_are_cleaned_up() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // CancelOnWrite cancels processing as soon as the first event, a file hashed inside the first archive, is written
+        struct CancelOnWrite(Arc<AtomicBool>);
+        impl std::io::Write for CancelOnWrite {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.store(true, Ordering::Relaxed);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // archives named uniquely, since their extractions are named after them in the shared temporary directory
+        let tmp = tempdir::TempDir::new("fvc_cancel_cleanup").expect("creating temporary directory");
+        let archives: Vec<PathBuf> = (0..3).map(|n| {
+            let archive = tmp.path().join(format!("cancel_cleanup_{}.tar.gz", n));
+            std::fs::copy("test_data/flat_archive.tar.gz", &archive).expect("copying archive");
+            archive
+        }).collect();
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = ProcessOptions {
+            progress: Some(std::sync::Arc::new(crate::events::EventLog::from_writer(CancelOnWrite(cancel.clone())))),
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+        let err = ExtractionProcessor::new(ExtractPolicy::Extension, options).calculate_fvc(&mut FVC2Hasher::new(), &archives).expect_err("processing should be cancelled");
+        assert!(err.get_ref().is_some_and(|inner| inner.is::<crate::process::Cancelled>()), "unexpected error: {}", err);
+
+        let leaked: Vec<_> = std::fs::read_dir(std::env::temp_dir()).expect("listing temporary directory")
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().contains("cancel_cleanup_"))
+            .map(|entry| entry.path())
+            .collect();
+        assert!(leaked.is_empty(), "extractions left behind: {:?}", leaked);
+    }
+
+    #[test]
+    fn archive_over_max_size_is_a_file() {
+        // flat_archive.tar.gz extracts to 11 bytes, foo.txt.gz to 4
+        let options = ProcessOptions { max_archive_size: Some(5), ..Default::default() };
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, options);
+        let collections = processor.collect(&[PathBuf::from("test_data/flat_archive.tar.gz"), PathBuf::from("test_data/foo.txt.gz")]).expect("processing archives");
+
+        match &collections[0] {
+            Collection::File(file) => assert_eq!(file.sha256, get_sha256("test_data/flat_archive.tar.gz").unwrap()),
+            other => panic!("expected a file, found {:?}", other)
+        };
+        assert!(matches!(&collections[1], Collection::Archive(_)), "found {:?}", collections[1]);
+    }
+
+    #[test]
+    fn audit_log_records_archive_decisions() {
+        let tmp = tempdir::TempDir::new("fvc_audit").expect("creating temporary directory");
+        let log_path = tmp.path().join("audit.ndjson");
+        let audit = crate::audit::AuditLog::create(&log_path).expect("creating audit log");
+
+        // flat_archive.tar.gz extracts to 11 bytes, over the maximum, while foo.txt.gz extracts to 4
+        let files = [PathBuf::from("test_data/flat_archive.tar.gz"), PathBuf::from("test_data/foo.txt.gz"), PathBuf::from("test_data/flat_files")];
+        let options = ProcessOptions { audit: Some(audit), max_archive_size: Some(5), ..Default::default() };
+        ExtractionProcessor::new(ExtractPolicy::Extension, options).collect(&files).expect("processing files");
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&log_path).expect("reading audit log").lines()
+            .map(|line| serde_json::from_str(line).expect("parsing record"))
+            .collect();
+        let decisions = |path: &str| records.iter().filter(|record| record["path"] == path).map(|record| record["decision"].as_str().unwrap().to_string()).collect::<Vec<String>>();
+        assert_eq!(decisions("test_data/flat_archive.tar.gz"), vec!["hashed"]);
+        assert_eq!(decisions("test_data/foo.txt.gz"), vec!["extracted"]);
+        assert_eq!(decisions("test_data/flat_files/foo.txt"), vec!["hashed"]);
+        assert!(records.iter().all(|record| record["reason"].is_string() && record["timestamp"].as_f64().is_some_and(|timestamp| timestamp > 0.0)), "records: {:?}", records);
+    }
+
+    #[test]
+    fn archive_comment_is_in_tree() {
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let collections = processor.collect(&[PathBuf::from("test_data/commented_archive.zip")]).expect("processing archive");
+        let tree = serde_json::to_value(&collections).expect("serializing tree");
+        assert_eq!(tree[0]["comment"], "built from commit 0123abc by release job 42");
+
+        // the comment is not part of the code
+        let mut hasher = FVC2Hasher::new();
+        processor.hash_collections(&mut hasher, &collections);
+        let mut foo = FVC2Hasher::new();
+        foo.read_sha256(get_sha256("test_data/flat_files/foo.txt").unwrap());
+        assert_eq!(hasher.hex(), foo.hex());
+
+        let uncommented = processor.collect(&[PathBuf::from("test_data/nested_archive.zip")]).expect("processing archive");
+        assert!(serde_json::to_value(&uncommented).unwrap()[0].get("comment").is_none());
+    }
+
+    #[test]
+    fn empty_archive_is_hashed_quietly() {
+        use sha2::Digest;
+
+        let tmp = tempdir::TempDir::new("fvc_empty_archive").expect("creating temporary directory");
+        let empty = tmp.path().join("foo.zip");
+        std::fs::write(&empty, b"").expect("writing empty archive");
+        let mut expected = FVC2Hasher::new();
+        expected.read_sha256(sha2::Sha256::digest(b"").into());
+
+        for policy in [ExtractPolicy::Extension, ExtractPolicy::All] {
+            let log_path = tmp.path().join("audit.ndjson");
+            let audit = crate::audit::AuditLog::create(&log_path).expect("creating audit log");
+            let mut hasher = FVC2Hasher::new();
+            ExtractionProcessor::new(policy, ProcessOptions { audit: Some(audit), ..Default::default() }).calculate_fvc(&mut hasher, std::slice::from_ref(&empty)).expect("processing empty archive");
+            assert_eq!(hasher.hex(), expected.hex());
+
+            // extraction is not even attempted, so it cannot fail
+            let records: Vec<serde_json::Value> = std::fs::read_to_string(&log_path).expect("reading audit log").lines()
+                .map(|line| serde_json::from_str(line).expect("parsing record"))
+                .collect();
+            assert_eq!(records.len(), 1, "records: {:?}", records);
+            assert_eq!(records[0]["decision"], "hashed");
+            assert_eq!(records[0]["reason"], "empty file");
+        }
+    }
+
+    #[test]
+    fn archive_file_is_hashed_instead_of_contents() {
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions { hash_archive_file: true, ..Default::default() });
+        let collections = processor.collect(&[PathBuf::from("test_data/nested_archive.zip")]).expect("processing nested archive");
+        let mut hasher = FVC2Hasher::new();
+        processor.hash_collections(&mut hasher, &collections);
+
+        // only the outer archive's sha256 is part of the code, while the tree still holds its nested archive and files
+        let mut archive = FVC2Hasher::new();
+        archive.read_sha256(get_sha256("test_data/nested_archive.zip").unwrap());
+        assert_eq!(hasher.hex(), archive.hex());
+        match &collections[0] {
+            Collection::Archive(outer) => assert_eq!(outer.archives.values().next().expect("nested archive").files.len(), 3),
+            other => panic!("expected an archive, found {:?}", other)
+        }
+    }
+
+    #[test]
+    fn package_payload_is_installed_files() {
+        use sha2::Digest;
+
+        let fvc = |options: ProcessOptions| {
+            let mut hasher = FVC2Hasher::new();
+            ExtractionProcessor::new(ExtractPolicy::Extension, options).calculate_fvc(&mut hasher, &[PathBuf::from("test_data/hello.deb")]).expect("processing package");
+            (hasher.len(), hasher.hex())
+        };
+        let mut installed = FVC2Hasher::new();
+        for content in ["hello\n", "world\n"] {
+            installed.read_sha256(sha2::Sha256::digest(content).into());
+        }
+
+        // debian-binary and the control file contribute along with the installed files
+        let (members, whole) = fvc(ProcessOptions::default());
+        assert_eq!(members, 4);
+        assert_ne!(whole, installed.hex());
+        assert_eq!(fvc(ProcessOptions { package_payload: true, ..Default::default() }), (2, installed.hex()));
+    }
+
+    #[test]
+    fn zip64_member_is_fully_hashed() {
+        use sha2::Digest;
+
+        // zip64_archive.zip stores its sizes in a zip64 extra field, the 32-bit fields of its local header being 0xffffffff
+        let content = b"0123456789abcdef\n".repeat(4096);
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        let collections = processor.collect(&[PathBuf::from("test_data/zip64_archive.zip")]).expect("processing zip64 archive");
+        let archive = match &collections[0] {
+            Collection::Archive(archive) => archive,
+            other => panic!("expected an archive, found {:?}", other)
+        };
+
+        let large = archive.files.get(Path::new("large.txt")).expect("finding large member");
+        assert_eq!(large.size, content.len() as u64);
+        assert_eq!(large.sha256, <[u8; 32]>::from(sha2::Sha256::digest(&content)));
+    }
+
+    #[test]
+    fn no_cycle_detection_gives_same_fvc() {
+        let files = [PathBuf::from("test_data/archive_hierarchy.tar.gz"), PathBuf::from("test_data/nested_archive.zip")];
+        let mut detected = FVC2Hasher::new();
+        ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default()).calculate_fvc(&mut detected, &files).expect("processing with cycle detection");
+
+        let options = ProcessOptions { no_cycle_detection: true, ..Default::default() };
+        let mut undetected = FVC2Hasher::new();
+        ExtractionProcessor::new(ExtractPolicy::Extension, options).calculate_fvc(&mut undetected, &files).expect("processing without cycle detection");
+        assert_eq!(detected.hex(), undetected.hex());
+    }
+
+    #[test]
+    fn uncompressed_size_is_metadata_only() {
+        // the same members, in a tar padded to the default 10240 bytes and in one that is not padded
+        let options = ProcessOptions { uncompressed_size: true, ..Default::default() };
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, options);
+        let archive = |path: &str| match processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, Path::new(path)).expect("processing archive") {
+            Collection::Archive(archive) => archive,
+            other => panic!("expected an archive, found {:?}", other)
+        };
+        let padded = archive("test_data/flat_archive.tar.gz");
+        let unpadded = archive("test_data/unpadded_archive.tar.gz");
+        assert_eq!(padded.uncompressed_size, Some(10240));
+        assert_eq!(unpadded.uncompressed_size, Some(4096));
+        assert_ne!(padded.metadata, unpadded.metadata);
+
+        let fvc = |files: &[&str]| {
+            let mut hasher = FVC2Hasher::new();
+            processor.calculate_fvc(&mut hasher, &files.iter().map(PathBuf::from).collect::<Vec<_>>()).expect("processing archive");
+            hasher.hex()
+        };
+        assert_eq!(fvc(&["test_data/flat_archive.tar.gz"]), fvc(&["test_data/unpadded_archive.tar.gz"]));
+    }
+
+    #[test]
+    fn single_file_archive_is_flattened() {
+        let path = Path::new("test_data/foo.txt.gz");
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        assert!(matches!(processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, path).expect("processing archive"), Collection::Archive(_)));
+
+        let options = ProcessOptions { flatten_single: true, ..Default::default() };
+        let flattening = ExtractionProcessor::new(ExtractPolicy::Extension, options);
+        match flattening.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, path).expect("processing archive") {
+            Collection::File(file) => {
+                assert_eq!(file.name, "foo.txt.gz");
+                assert_eq!(file.sha256, get_sha256("test_data/flat_files/foo.txt").unwrap());
+            },
+            other => panic!("expected a file, found {:?}", other)
+        };
+
+        // the code is that of the file either way
+        let fvc = |processor: &ExtractionProcessor| {
+            let mut hasher = FVC2Hasher::new();
+            processor.calculate_fvc(&mut hasher, &[path.to_path_buf()]).expect("processing archive");
+            hasher.hex()
+        };
+        assert_eq!(fvc(&processor), fvc(&flattening));
+    }
+
+    #[test]
+    fn truncated_gzip_is_reported() {
+        let gzip = std::fs::read("test_data/foo.txt.gz").unwrap();
+        let tmp = tempdir::TempDir::new("fvc_truncated").expect("creating temporary directory");
+        let path = tmp.path().join("foo.txt.gz");
+        std::fs::write(&path, &gzip[..gzip.len() / 2]).unwrap();
+        assert_eq!(extract::compressed_stream(&path).unwrap(), Some("gzip"));
+
+        let options = ProcessOptions { strict_extract: true, ..Default::default() };
+        let err = ExtractionProcessor::new(ExtractPolicy::Extension, options).calculate_fvc(&mut FVC2Hasher::new(), std::slice::from_ref(&path)).expect_err("truncated gzip should be an error");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("truncated or corrupt gzip stream"), "unexpected error: {}", err);
+
+        // without strict extraction it is hashed as a file, as before
+        let processor = ExtractionProcessor::new(ExtractPolicy::Extension, ProcessOptions::default());
+        assert!(matches!(processor.calculate_fvc_of(&mut ArchiveGraph::new(), None, 0, &path).expect("processing truncated gzip"), Collection::File(_)));
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Describe the files that contributed to a code as a software bill of materials, for existing SBOM pipelines
+//! Only the fields needed to carry every file's sha256, and the code itself, are written
+
+use file_verification_code::archive_tree::{self, Collection};
+
+use serde::Serialize;
+
+// CycloneDX version the document follows
+const CYCLONEDX_SPEC_VERSION: &str = "1.5";
+
+/// CycloneDX is a minimal CycloneDX document
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CycloneDX {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: Metadata,
+    components: Vec<Component>,
+}
+
+#[derive(Debug, Serialize)]
+struct Metadata {
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Serialize)]
+struct Property {
+    name: &'static str,
+    value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Component {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: String,
+    hashes: Vec<Hash>,
+}
+
+#[derive(Debug, Serialize)]
+struct Hash {
+    alg: &'static str,
+    content: String,
+}
+
+/// cyclonedx lists every file that contributed to the given trees as a file component with its sha256, and the code as the `fvc` property of the document
+/// Files in archives are named by their path in the archive under the archive's path, as in archive_tree::contributions
+pub fn cyclonedx(collections: &[Collection], fvc: &str) -> CycloneDX {
+    let components = collections.iter()
+        .flat_map(archive_tree::contributions)
+        .map(|(path, sha256)| Component {
+            kind: "file",
+            name: path.display().to_string(),
+            hashes: vec![Hash { alg: "SHA-256", content: hex::encode(sha256) }],
+        })
+        .collect();
+
+    CycloneDX {
+        bom_format: "CycloneDX",
+        spec_version: CYCLONEDX_SPEC_VERSION,
+        version: 1,
+        metadata: Metadata { properties: vec![Property { name: "fvc", value: fvc.to_string() }] },
+        components,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{self, ProcessOptions};
+    use file_verification_code::{FVCHasher, FVC2Hasher};
+    use std::path::PathBuf;
+
+    #[test]
+    fn cyclonedx_lists_every_file() {
+        let processor = process::new(process::default_policy(), ProcessOptions::default());
+        let collections = processor.collect(&[PathBuf::from("test_data/flat_files"), PathBuf::from("test_data/nested_archive.zip")]).expect("processing inputs");
+        let mut hasher = FVC2Hasher::new();
+        processor.hash_collections(&mut hasher, &collections);
+
+        let sbom = serde_json::to_value(cyclonedx(&collections, &hasher.hex())).expect("serializing sbom");
+        assert_eq!(sbom["bomFormat"], "CycloneDX");
+        assert_eq!(sbom["metadata"]["properties"][0]["name"], "fvc");
+        assert_eq!(sbom["metadata"]["properties"][0]["value"], hasher.hex());
+        let components = sbom["components"].as_array().expect("listing components");
+        assert_eq!(components.len(), 6);
+        assert!(components.iter().all(|component| component["type"] == "file" && component["hashes"][0]["alg"] == "SHA-256"));
+        let foo = components.iter().find(|component| component["name"] == "test_data/flat_files/foo.txt").expect("finding foo.txt");
+        assert_eq!(foo["hashes"][0]["content"], "b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c");
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Check this build against the known FVC2 vectors of the test_data fixtures, which are built into the binary
+//! so a platform-specific sha2 or extraction regression shows up wherever fvc is installed
+
+use crate::process::{self, ProcessOptions, Processor};
+use file_verification_code::{FVCHasher, FVC2Hasher};
+
+// Vector is a set of fixture files, named as they are written out, and the code processing them must give
+struct Vector {
+    name: &'static str,
+    files: &'static [(&'static str, &'static [u8])],
+    expected: &'static str,
+}
+
+const FLAT_FILES: &[(&str, &[u8])] = &[
+    ("foo.txt", include_bytes!("../../test_data/flat_files/foo.txt")),
+    ("bar.txt", include_bytes!("../../test_data/flat_files/bar.txt")),
+    ("zap.txt", include_bytes!("../../test_data/flat_files/zap.txt")),
+];
+
+const VECTORS: &[Vector] = &[
+    Vector { name: "flat files", files: FLAT_FILES, expected: include_str!("../../test_data/flat_files.fvc2.hex") },
+    #[cfg(feature = "extract")]
+    Vector { name: "flat archive", files: &[("flat_archive.tar.gz", include_bytes!("../../test_data/flat_archive.tar.gz"))], expected: include_str!("../../test_data/flat_files.fvc2.hex") },
+    #[cfg(feature = "extract")]
+    Vector { name: "nested archive", files: &[("nested_archive.zip", include_bytes!("../../test_data/nested_archive.zip"))], expected: include_str!("../../test_data/flat_files.fvc2.hex") },
+    #[cfg(feature = "extract")]
+    Vector { name: "archive hierarchy", files: &[("archive_hierarchy.tar.gz", include_bytes!("../../test_data/archive_hierarchy.tar.gz"))], expected: include_str!("../../test_data/archive_hierarchy.fvc2.hex") },
+];
+
+/// run processes every known vector with the default options, printing whether each gives its expected code
+/// It returns whether all of them did
+pub fn run() -> std::io::Result<bool> {
+    let mut passed = true;
+    for vector in VECTORS {
+        let got = code_of(vector)?;
+        if got == vector.expected.trim() {
+            println!("ok      {}", vector.name);
+        } else {
+            println!("FAILED  {}: expected {}, got {}", vector.name, vector.expected.trim(), got);
+            passed = false;
+        }
+    }
+
+    Ok(passed)
+}
+
+// code_of writes the files of vector to a temporary directory and processes it
+fn code_of(vector: &Vector) -> std::io::Result<String> {
+    let tmp = tempdir::TempDir::new("fvc_selftest")?;
+    for (name, content) in vector.files {
+        std::fs::write(tmp.path().join(name), content)?;
+    }
+
+    let mut hasher = FVC2Hasher::new();
+    process::new(process::default_policy(), ProcessOptions::default()).calculate_fvc(&mut hasher, &[tmp.path().to_path_buf()])?;
+
+    Ok(hasher.hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_vectors_pass() {
+        assert!(run().expect("running selftest"));
+    }
+}
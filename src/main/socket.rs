@@ -0,0 +1,63 @@
+// Copyright (c) 2020 Wind River Systems, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at:
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES
+// OR CONDITIONS OF ANY KIND, either express or implied.
+
+//! Receive the bytes to process from a Unix domain socket, for build systems that hand artifacts over IPC
+//! The stream is saved as a file, so it is processed like any given file, and extracted if the extraction policy allows
+
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use log::*;
+
+// name of the file the stream is saved to, it has no extension so only `--extract all` tries it as an archive
+const STREAM_FILE_NAME: &str = "stream";
+
+/// receive connects to the socket at path, reads the stream until the other end closes it, and saves it in dir
+/// It returns the path of the saved file
+pub fn receive(path: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+    let mut stream = UnixStream::connect(path)?;
+    let received = dir.join(STREAM_FILE_NAME);
+    let mut file = std::fs::File::create(&received)?;
+    let size = std::io::copy(&mut stream, &mut file)?;
+    debug!("received {} bytes from {}", size, path.display());
+
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{self, Processor, ProcessOptions};
+    use file_verification_code::{FVCHasher, FVCSha256Hasher, FVC2Hasher};
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn stream_is_single_member() {
+        use sha2::{Sha256, Digest};
+
+        let bytes = b"bytes handed over a socket\n";
+        let tmp = tempdir::TempDir::new("fvc_socket").expect("creating temporary directory");
+        let socket_path = tmp.path().join("artifact.sock");
+        let listener = UnixListener::bind(&socket_path).expect("binding socket");
+        let server = std::thread::spawn(move || {
+            let (mut connection, _) = listener.accept().expect("accepting connection");
+            connection.write_all(bytes).expect("writing to socket");
+        });
+
+        let received = receive(&socket_path, tmp.path()).expect("receiving stream");
+        server.join().expect("joining server");
+        let mut hasher = FVC2Hasher::new();
+        process::new(process::default_policy(), ProcessOptions::default()).calculate_fvc(&mut hasher, &[received]).expect("processing stream");
+
+        let mut expected = FVC2Hasher::new();
+        expected.read_sha256(Sha256::digest(bytes).into());
+        assert_eq!(hasher.hex(), expected.hex());
+    }
+}